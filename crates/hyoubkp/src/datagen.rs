@@ -1,7 +1,9 @@
-use hyoubkp_base::{datagen::DataGen, transaction::Transaction};
+use hyoubkp_base::{bail, datagen::DataGen, transaction::Transaction};
 
 #[cfg(feature = "datagen_gnucash")]
 pub use hyoubkp_datagen_gnucash as datagen_impl_gnucash;
+#[cfg(feature = "datagen_beancount")]
+pub use hyoubkp_datagen_beancount as datagen_impl_beancount;
 
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
@@ -11,6 +13,8 @@ pub enum DataGenKind {
     #[cfg(feature = "datagen_gnucash")]
     #[cfg_attr(feature = "clap", clap(name = "gnucash"))]
     GnuCash = 1,
+    #[cfg(feature = "datagen_beancount")]
+    Beancount = 2,
 }
 
 #[derive(Debug)]
@@ -18,6 +22,8 @@ pub enum DataGenDispatch {
     Str(DataGenImplStr),
     #[cfg(feature = "datagen_gnucash")]
     GnuCash(datagen_impl_gnucash::DataGenImpl),
+    #[cfg(feature = "datagen_beancount")]
+    Beancount(datagen_impl_beancount::DataGenImpl),
 }
 
 impl DataGenDispatch {
@@ -26,6 +32,23 @@ impl DataGenDispatch {
             DataGenKind::Str => Self::Str(DataGenImplStr {}),
             #[cfg(feature = "datagen_gnucash")]
             DataGenKind::GnuCash => Self::GnuCash(datagen_impl_gnucash::DataGenImpl::new()),
+            #[cfg(feature = "datagen_beancount")]
+            DataGenKind::Beancount => {
+                Self::Beancount(datagen_impl_beancount::DataGenImpl::new())
+            }
+        }
+    }
+
+    /// Sets a backend option given as `key=value`
+    pub fn set_option(&mut self, option: &str) -> hyoubkp_base::error::Result<()> {
+        let Some((key, value)) = option.split_once('=') else {
+            bail!("Data-gen option '{}' is not in the form key=value", option);
+        };
+
+        match self {
+            #[cfg(feature = "datagen_beancount")]
+            Self::Beancount(d) => d.set_option(key.trim(), value.trim()),
+            _ => bail!("Data-gen backend has no option '{}'", key.trim()),
         }
     }
 }
@@ -36,6 +59,8 @@ macro_rules! dispatch {
             DataGenDispatch::Str($n) => $($e)*,
             #[cfg(feature = "datagen_gnucash")]
             DataGenDispatch::GnuCash($n) => $($e)*,
+            #[cfg(feature = "datagen_beancount")]
+            DataGenDispatch::Beancount($n) => $($e)*,
         }
     };
 }
@@ -66,6 +91,16 @@ impl DataGen for DataGenDispatch {
     ) -> Result<(), std::io::Error> {
         dispatch!(self, d, d.write_to(f, transactions, number))
     }
+
+    fn rewrite(
+        &self,
+        input: impl std::io::Read,
+        f: impl std::io::Write,
+        id: &str,
+        replacement: Option<&Transaction>,
+    ) -> Result<(), std::io::Error> {
+        dispatch!(self, d, d.rewrite(input, f, id, replacement))
+    }
 }
 
 #[derive(Debug)]
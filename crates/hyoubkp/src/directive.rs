@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use hyoubkp_base::date::Date;
+use hyoubkp_base::directive::DirectiveInfo;
+use hyoubkp_base::error::{Error, Result};
+use hyoubkp_base::{bail, err};
+
+use crate::executor::{split_account_amount, Executor};
+
+pub type DirectiveHandler = Arc<dyn Fn(&mut Executor, &str) -> Result<()> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Directive {
+    pub info: DirectiveInfo,
+    pub handler: DirectiveHandler,
+}
+
+impl Directive {
+    pub fn new(
+        name: impl Into<String>,
+        usage: impl Into<String>,
+        help: impl Into<String>,
+        handler: impl Fn(&mut Executor, &str) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            info: DirectiveInfo::new(name, usage, help),
+            handler: Arc::new(handler),
+        }
+    }
+
+    /// Adds the usage of the directive to an error of its handler
+    pub(crate) fn with_usage(&self, e: Error) -> Error {
+        err!("{}\nUsage: {}\n    {}", e.message, self.info, self.info.help)
+    }
+}
+
+impl std::fmt::Debug for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Directive").field("info", &self.info).finish()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DirectiveRegistry {
+    directives: Vec<Directive>,
+}
+
+impl DirectiveRegistry {
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+
+        registry.register(Directive::new(
+            "date",
+            "YYYY-MM-DD",
+            "Set the date of the following transactions",
+            |ex, args| {
+                ex.set_date(args.parse::<Date>()?);
+                Ok(())
+            },
+        ));
+        registry.register(Directive::new(
+            "num",
+            "NUMBER",
+            "Set the base of the transaction numbers",
+            |ex, args| {
+                ex.set_num_base(args.parse()?);
+                Ok(())
+            },
+        ));
+        registry.register(Directive::new(
+            "status",
+            "none|pending|cleared|reconciled",
+            "Set the default status of the following transactions",
+            |ex, args| {
+                ex.set_status(args.parse()?);
+                Ok(())
+            },
+        ));
+        registry.register(Directive::new(
+            "set",
+            "NAME=PRICE",
+            "Set the value of $NAME or ${NAME} in prices",
+            |ex, args| {
+                let (name, value) = split_assignment(args, "NAME=PRICE")?;
+                ex.set_variable(name, value.parse()?)
+            },
+        ));
+        registry.register(Directive::new(
+            "macro",
+            "NAME = EXPR",
+            "Define a transaction beginning with NAME as EXPR, \
+            where $1, $2... are the words following NAME",
+            |ex, args| {
+                let (name, template) = split_assignment(args, "NAME = EXPR")?;
+                ex.set_macro(name, template)
+            },
+        ));
+        registry.register(Directive::new(
+            "every",
+            "day|week WEEKDAY|month DAY|year MM-DD EXPR",
+            "Schedule EXPR to recur, WEEKDAY is 1 (Monday) to 7, \
+            and DAY past the end of a month falls on its last day",
+            |ex, args| ex.add_schedule(args.parse()?),
+        ));
+        registry.register(Directive::new(
+            "interest",
+            "FROM [TO]",
+            "Accrue interest from FROM to TO, default the current date, on the \
+            accounts with interest terms, and commit the interest transactions",
+            |ex, args| {
+                let mut dates = args.split_whitespace();
+                let from = dates.next().unwrap_or_default().parse::<Date>()?;
+                let to = match dates.next() {
+                    Some(to) => to.parse::<Date>()?,
+                    None => ex.get_date(),
+                };
+                ex.accrue_interest(from, to).map(|_| ())
+            },
+        ));
+        registry.register(Directive::new(
+            "balance",
+            "ACCOUNT AMOUNT",
+            "Assert the running balance of an account token or full account name, \
+            debits are positive",
+            |ex, args| {
+                let (account, amount) = split_account_amount(args)?;
+                ex.assert_balance(account, amount)
+            },
+        ));
+
+        registry
+    }
+
+    /// Registers a directive, replacing the one with the same name
+    pub fn register(&mut self, directive: Directive) {
+        match self
+            .directives
+            .iter_mut()
+            .find(|d| d.info.name == directive.info.name)
+        {
+            Some(d) => *d = directive,
+            None => self.directives.push(directive),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Directive> {
+        self.directives.iter().find(|d| d.info.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DirectiveInfo> {
+        self.directives.iter().map(|d| &d.info)
+    }
+}
+
+/// Splits the `NAME=VALUE` arguments of `.set` and `.macro` at the first `=`
+pub(crate) fn split_assignment<'a>(args: &'a str, usage: &str) -> Result<(&'a str, &'a str)> {
+    match args.split_once(['=', '＝']) {
+        Some((name, value)) => Ok((name.trim(), value.trim())),
+        None => bail!("Expected '{}', but got '{}'", usage, args),
+    }
+}
+
+/// Splits `.name args` into the name and the trimmed arguments
+pub fn split_directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim().strip_prefix('.')?;
+
+    Some(match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    })
+}
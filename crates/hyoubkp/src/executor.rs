@@ -1,67 +1,525 @@
 use std::collections::HashMap;
 
+use crate::directive::{split_assignment, split_directive, Directive, DirectiveRegistry};
+use crate::interest;
+use crate::ledger::Ledger;
+use crate::lots::{Lot, Lots};
 use crate::parser::{parse_fail, ParseError, ParseResult, Parser};
+use crate::reimbursement::{open_items, OpenItem};
+use crate::schedule::{OpenInstallment, Schedule};
 use crate::tokmap::{tokmap_dispatch, TokenMapperDispatch, TokenMapperKind};
 use hyoubkp_base::date::Date;
-use hyoubkp_base::tokmap::{TokenMapper, TokenMapperOption};
-use hyoubkp_base::transaction::Transaction;
+use hyoubkp_base::directive::DirectiveInfo;
+use hyoubkp_base::expr::CompoundExpr;
+use hyoubkp_base::tokmap::{
+    InterestContext, InterestTerms, StatementCycle, TokenMapper, TokenMapperOption,
+    REDEMPTION_HINT, REIMBURSEMENT_HINT,
+};
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{Amount, Commodity, Entry, Transaction, TransactionStatus};
 use hyoubkp_base::transaction::TransactionFactory;
 use hyoubkp_base::error::Result;
+use hyoubkp_base::{bail, err};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Executor {
     pub token_mapper: TokenMapperDispatch,
     pub parser: Parser,
+    pub directives: DirectiveRegistry,
 
     date: ExecutorDate,
     num_base: u32,
+    status: TransactionStatus,
+    post_imbalance: bool,
+    merge_entries: bool,
+    ledger: Option<Ledger>,
+    /// Lots held before the first transaction, from the opening balances
+    opening_lots: Lots,
+    journal: Vec<Transaction>,
+    schedules: Vec<Schedule>,
+    statement_cycles: Vec<StatementCycle>,
+    interest_terms: Vec<InterestTerms>,
+    /// Interest periods `from..to` accrued per account token
+    accrued_interest: Vec<(String, Date, Date)>,
+    /// Interest period of each committed interest transaction, by id
+    interest_periods: HashMap<String, (String, Date, Date)>,
+    /// Installment plans of purchases in previous sessions
+    installments: Vec<OpenInstallment>,
 }
 
 impl Executor {
     pub fn new(tokmap_kind: TokenMapperKind, options: &HashMap<TokenMapperOption, String>) -> Result<Self> {
         let token_mapper = TokenMapperDispatch::new(tokmap_kind, options)?;
+        let mut hint_tokens = tokmap_dispatch!(tm, &token_mapper, tm.register_hint_tokens());
+        hint_tokens.push(REIMBURSEMENT_HINT);
+        hint_tokens.push(REDEMPTION_HINT);
+
         let parser = Parser::new(
             &tokmap_dispatch!(tm, &token_mapper, tm.register_account_tokens()),
-            &tokmap_dispatch!(tm, &token_mapper, tm.register_hint_tokens()),
+            &hint_tokens,
         );
+        let variables = tokmap_dispatch!(tm, &token_mapper, tm.register_variables());
+        let macros = tokmap_dispatch!(tm, &token_mapper, tm.register_macros());
+
+        let statement_cycles =
+            tokmap_dispatch!(tm, &token_mapper, tm.register_statement_cycles());
+        let interest_terms = tokmap_dispatch!(tm, &token_mapper, tm.register_interest_terms());
+
+        let mut directives = DirectiveRegistry::builtin();
+        for info in tokmap_dispatch!(tm, &token_mapper, tm.register_directives()) {
+            let name = info.name.clone();
+            directives.register(Directive {
+                info,
+                handler: std::sync::Arc::new(move |ex: &mut Executor, args: &str| {
+                    tokmap_dispatch!(mut tm, &mut ex.token_mapper, tm.on_directive(&name, args))
+                }),
+            });
+        }
 
-        Ok(Self {
+        let mut executor = Self {
             token_mapper,
             parser,
+            directives,
             date: ExecutorDate::default(),
             num_base: 0,
-        })
+            status: TransactionStatus::default(),
+            post_imbalance: false,
+            merge_entries: false,
+            ledger: None,
+            opening_lots: Lots::default(),
+            journal: Vec::new(),
+            schedules: Vec::new(),
+            statement_cycles,
+            interest_terms,
+            accrued_interest: Vec::new(),
+            interest_periods: HashMap::new(),
+            installments: Vec::new(),
+        };
+
+        // Validated the same as `.set` and `.macro`
+        for (name, value) in variables {
+            executor.set_variable(&name, value)?;
+        }
+        for (name, template) in macros {
+            executor.set_macro(&name, &template)?;
+        }
+
+        Ok(executor)
     }
 
-    pub fn parse_expr(&mut self, expr: impl AsRef<str>) -> ParseResult<Transaction> {
-        self.parser.reset();
-        let cexpr = self.parser.parse_expr(expr.as_ref())?;
+    /// Builds the transactions of an input line without any side effect
+    pub fn preview(&self, expr: impl AsRef<str>) -> ParseResult<Vec<Transaction>> {
+        self.preview_in(&self.parser, &self.directive_state(), &self.journal, expr.as_ref())
+    }
 
-        let mut factory: TransactionFactory = TransactionFactory::default();
+    /// Same as [`Executor::preview`], as of a directive previewed with
+    /// [`Executor::preview_directive`]
+    pub fn preview_with(
+        &self,
+        directive: &DirectivePreview,
+        expr: impl AsRef<str>,
+    ) -> ParseResult<Vec<Transaction>> {
+        self.preview_in(&directive.parser, &directive.state, &self.journal, expr.as_ref())
+    }
+
+    /// Builds the transactions of `expr` as if committed after `journal`
+    fn preview_in(
+        &self,
+        parser: &Parser,
+        state: &DirectiveState,
+        journal: &[Transaction],
+        expr: &str,
+    ) -> ParseResult<Vec<Transaction>> {
+        let cexprs = parser.parse_expr(expr)?;
 
-        if cexpr.exprs.is_empty() && cexpr.comment.is_none() {
+        if cexprs.is_empty() {
             parse_fail!('\0', 0, "Expression can not be parsed")
         }
 
+        let mut lots = self.held_lots(journal);
+        let mut transactions: Vec<Transaction> = cexprs
+            .into_iter()
+            .map(|cexpr| self.build_transaction(cexpr, state, &mut lots))
+            .collect();
+
+        if transactions.iter().any(|t| !t.reimbursements.is_empty()) {
+            let mut journal = journal.to_vec();
+            let mut seen = open_items(&journal).1.len();
+
+            for transaction in transactions.iter_mut() {
+                journal.push(transaction.clone());
+                let (_, excess) = open_items(&journal);
+                for (account, amount) in excess[seen..].iter() {
+                    transaction.diagnostics.push(format!(
+                        "Settled {} more than claimed to {}",
+                        amount, account
+                    ));
+                }
+                if excess.len() > seen && !transaction.has_build_error {
+                    transaction.has_build_error = true;
+                    transaction.description = Some(format!(
+                        "{} FIXME:[{}]",
+                        transaction.description.as_deref().unwrap_or_default(),
+                        transaction.orig_expr.as_deref().unwrap_or_default()
+                    ));
+                }
+                seen = excess.len();
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Lots of shares held by `account` after the committed transactions, oldest first
+    pub fn lots(&self, account: &str) -> Vec<Lot> {
+        self.held_lots(&self.journal).lots(account).to_vec()
+    }
+
+    fn held_lots(&self, journal: &[Transaction]) -> Lots {
+        let mut lots = self.opening_lots.clone();
+        for transaction in journal.iter() {
+            lots.post(transaction);
+        }
+
+        lots
+    }
+
+    /// Claims with [`REIMBURSEMENT_HINT`] not settled yet, oldest first
+    pub fn open_reimbursements(&self) -> Vec<OpenItem> {
+        open_items(&self.journal).0
+    }
+
+    /// Builds the transactions of an input line and records them in the journal
+    pub fn commit(&mut self, expr: impl AsRef<str>) -> ParseResult<Vec<Transaction>> {
+        let transactions = self.preview(expr)?;
+
+        Ok(transactions
+            .into_iter()
+            .map(|transaction| {
+                if let Some(expr) = &transaction.orig_expr {
+                    self.parser.push_history(expr.clone());
+                }
+                self.commit_transaction(transaction)
+            })
+            .collect())
+    }
+
+    /// Records a transaction built elsewhere, e.g. an installment move, in the
+    /// journal and the ledger
+    pub fn commit_transaction(&mut self, mut transaction: Transaction) -> Transaction {
+        transaction.id = Some(uuid::Uuid::new_v4().as_simple().to_string());
+
+        if let Some(ledger) = &mut self.ledger {
+            ledger.post(&transaction);
+        }
+        self.journal.push(transaction.clone());
+
+        transaction
+    }
+
+    /// Installment plans of the committed transactions, keyed by the id of
+    /// the purchase, to be remembered in [`crate::schedule::ScheduleState`]
+    pub fn open_installments(&self) -> Vec<OpenInstallment> {
+        let mut installments = Vec::new();
+
+        for transaction in self.journal.iter() {
+            for (i, plan) in transaction.installments.iter().enumerate() {
+                installments.push(OpenInstallment {
+                    key: format!(
+                        "installment {}#{}",
+                        transaction.id.as_deref().unwrap_or_default(),
+                        i
+                    ),
+                    date: transaction.date,
+                    num_base: transaction.num_base,
+                    description: transaction.description.clone(),
+                    plan: plan.clone(),
+                });
+            }
+        }
+
+        installments
+    }
+
+    /// Restores the installment plans of previous sessions
+    pub fn load_installments(&mut self, installments: impl IntoIterator<Item = OpenInstallment>) {
+        for installment in installments {
+            if !self.installments.iter().any(|i| i.key == installment.key) {
+                self.installments.push(installment);
+            }
+        }
+    }
+
+    /// Installment moves due in `from..=to` of the plans loaded and of the
+    /// committed transactions, ordered by date. Each comes with the key of its
+    /// plan, to remember the moves generated, see [`crate::schedule::ScheduleState`]
+    pub fn due_installments(&self, from: Date, to: Date) -> Vec<(Date, String, Transaction)> {
+        let mut due = Vec::new();
+        let open = self.open_installments();
+        let loaded = self.installments.iter().filter(|i| !open.iter().any(|o| o.key == i.key));
+
+        for installment in loaded.chain(open.iter()) {
+            for (date, mut moved) in installment.due_moves(from, to) {
+                moved.status = self.status;
+                due.push((date, installment.key.clone(), moved));
+            }
+        }
+        due.sort_by_key(|(date, _, _)| *date);
+
+        due
+    }
+
+    /// Commits the expression of `schedule` as of `date`, the date of the
+    /// following transactions is kept
+    pub fn commit_scheduled(
+        &mut self,
+        schedule: &Schedule,
+        date: Date,
+    ) -> ParseResult<Vec<Transaction>> {
+        let saved = std::mem::replace(&mut self.date, ExecutorDate::Fixed(date));
+        let result = self.commit(&schedule.expr);
+        self.date = saved;
+
+        result
+    }
+
+    /// Transactions committed so far, in order
+    pub fn journal(&self) -> &[Transaction] {
+        &self.journal
+    }
+
+    /// Removes the last committed transaction and returns it
+    pub fn undo_last(&mut self) -> Option<Transaction> {
+        let transaction = self.journal.pop()?;
+        if self.parser.history().last() == transaction.orig_expr.as_ref() {
+            self.parser.pop_history();
+        }
+        let period = transaction.id.as_ref().and_then(|id| self.interest_periods.remove(id));
+        if let Some(period) = period {
+            self.accrued_interest.retain(|p| *p != period);
+        }
+        self.rebuild_ledger();
+        Some(transaction)
+    }
+
+    /// Expressions committed so far, after `!!` and `!TOKEN` are expanded
+    pub fn history(&self) -> &[String] {
+        self.parser.history()
+    }
+
+    /// Restores the history of a previous session, oldest first
+    pub fn load_history(&mut self, history: impl IntoIterator<Item = String>) {
+        for expr in history {
+            self.parser.push_history(expr);
+        }
+    }
+
+    /// Replaces the `n`-th committed transaction with one built from `expr`,
+    /// keeping its id, date and number base. The later sales of shares are
+    /// rebuilt on the lots left. Returns the replaced transaction
+    pub fn amend(&mut self, n: usize, expr: impl AsRef<str>) -> Result<Transaction> {
+        let transaction = self.preview_amend(n, expr)?;
+        let orig = std::mem::replace(&mut self.journal[n], transaction);
+        self.rebuild_sales(n + 1);
+        self.rebuild_ledger();
+
+        Ok(orig)
+    }
+
+    /// Same as [`Executor::amend`], but returns the replacement without committing it.
+    /// It is built on the lots held before the `n`-th transaction
+    pub fn preview_amend(&self, n: usize, expr: impl AsRef<str>) -> Result<Transaction> {
+        if n >= self.journal.len() {
+            bail!("Transaction {} is not committed, the journal has {}", n, self.journal.len());
+        }
+
+        let orig = &self.journal[n];
+        let state = DirectiveState {
+            date: ExecutorDate::Fixed(orig.date),
+            num_base: orig.num_base,
+            status: self.status,
+        };
+        let mut transactions = self
+            .preview_in(&self.parser, &state, &self.journal[..n], expr.as_ref())
+            .map_err(|e| err!("{}", e.to_string().trim_end()))?;
+        if transactions.len() != 1 {
+            bail!("Amending expression must build exactly one transaction");
+        }
+
+        let mut transaction = transactions.pop().unwrap();
+        transaction.id = orig.id.clone();
+
+        Ok(transaction)
+    }
+
+    /// Rebuilds the sales of shares from the `n`-th transaction on, as the lots
+    /// they are taken from may have changed. Sales not built from an expression
+    /// are kept
+    fn rebuild_sales(&mut self, n: usize) {
+        let mut lots = self.held_lots(&self.journal[..n]);
+
+        for i in n..self.journal.len() {
+            let orig = &self.journal[i];
+            let sold = orig
+                .credit_entries
+                .iter()
+                .any(|e| matches!(e.amount, Amount::Lot(..) | Amount::Shares(..)));
+            let cexpr = match &orig.orig_expr {
+                Some(expr) if sold => self.parser.parse_expr(expr).ok().and_then(|mut cexprs| {
+                    (cexprs.len() == 1).then(|| cexprs.pop().unwrap())
+                }),
+                _ => None,
+            };
+
+            let Some(cexpr) = cexpr else {
+                lots.post(orig);
+                continue;
+            };
+            let state = DirectiveState {
+                date: ExecutorDate::Fixed(orig.date),
+                num_base: orig.num_base,
+                status: orig.status,
+            };
+            let mut transaction = self.build_transaction(cexpr, &state, &mut lots);
+            transaction.id = orig.id.clone();
+            transaction.status = orig.status;
+            self.journal[i] = transaction;
+        }
+    }
+
+    fn rebuild_ledger(&mut self) {
+        if let Some(ledger) = &mut self.ledger {
+            ledger.clear_postings();
+
+            for transaction in self.journal.iter() {
+                ledger.post(transaction);
+            }
+        }
+    }
+
+    /// Same as [`Executor::commit`]
+    pub fn parse_expr(&mut self, expr: impl AsRef<str>) -> ParseResult<Vec<Transaction>> {
+        self.commit(expr)
+    }
+
+    fn build_transaction(
+        &self,
+        cexpr: CompoundExpr,
+        state: &DirectiveState,
+        lots: &mut Lots,
+    ) -> Transaction {
+        let mut factory: TransactionFactory = TransactionFactory::default();
+        factory.set_post_imbalance(self.post_imbalance);
+        factory.set_date(state.date.get_date());
+
         for expr in cexpr.exprs.iter() {
             tokmap_dispatch!(tm, &self.token_mapper, factory.set_expr(tm, expr))
         }
 
         let mut transaction = tokmap_dispatch!(tm, &self.token_mapper, factory.build(tm));
 
-        transaction.date = self.date.get_date();
-        transaction.num_base = self.num_base;
-        transaction.orig_expr = Some(expr.as_ref().to_string());
+        if !transaction.sales.is_empty() {
+            let prefer = match cexpr.metadata.iter().find(|(key, _)| key == "lot") {
+                Some((_, date)) => match date.parse() {
+                    Ok(date) => Some(date),
+                    Err(_) => {
+                        transaction
+                            .diagnostics
+                            .push(format!("Invalid lot date '{}', sold oldest first", date));
+                        None
+                    }
+                },
+                None => None,
+            };
+            Self::sell_lots(&mut transaction, lots, prefer);
+        }
+
+        if self.merge_entries {
+            transaction.merge_entries();
+        }
+
+        transaction.date = state.date.get_date();
+        transaction.num_base = state.num_base;
+        transaction.status = cexpr.status.unwrap_or(state.status);
 
         if transaction.has_build_error {
             transaction.description = Some(
-                cexpr.comment.unwrap_or_default() + " FIXME:[" + expr.as_ref() + "]",
+                cexpr.comment.unwrap_or_default() + " FIXME:[" + &cexpr.source + "]",
             );
         } else {
             transaction.description = Some(cexpr.comment.unwrap_or_default());
         }
 
-        Ok(transaction)
+        transaction.orig_expr = Some(cexpr.source);
+        transaction.payee = cexpr.payee;
+        transaction.tags = cexpr.tags;
+        transaction.metadata = cexpr.metadata;
+
+        // The lots sold are already taken by `sell_lots`
+        lots.buy(&transaction);
+
+        transaction
+    }
+
+    /// Replaces each entry of shares sold with the lots they are taken from at
+    /// cost, and books the difference to the proceeds as a realised gain or loss
+    fn sell_lots(transaction: &mut Transaction, lots: &mut Lots, prefer: Option<Date>) {
+        // Sales are in order, each replaces its entry with the lots sold
+        let (mut added, mut removed) = (0, 0);
+
+        for sale in std::mem::take(&mut transaction.sales) {
+            let index = sale.entry + added - removed;
+            let sold = transaction.credit_entries.remove(index);
+            let Amount::Shares(shares, proceeds) = sold.amount else {
+                transaction.credit_entries.insert(index, sold);
+                continue;
+            };
+
+            let (selected, missing) = lots.sell(&sold.account, shares, prefer);
+            let mut cost = Price::default();
+            let mut entries = Vec::new();
+
+            for lot in selected {
+                cost += lot.cost;
+                entries.push(Entry {
+                    account: sold.account.clone(),
+                    amount: Amount::Lot(lot.shares, lot.cost, lot.date),
+                    memo: sold.memo.clone(),
+                });
+            }
+            // Shares not held have no known cost, and make the gain wrong
+            if missing != Price::default() {
+                transaction.has_build_error = true;
+                transaction.diagnostics.push(format!(
+                    "Sold {} shares more than held in {}",
+                    missing, sold.account
+                ));
+                entries.push(Entry {
+                    account: sold.account.clone(),
+                    amount: Amount::Shares(missing, Price::default()),
+                    memo: sold.memo.clone(),
+                });
+            }
+
+            added += entries.len();
+            removed += 1;
+            transaction.credit_entries.splice(index..index, entries);
+
+            let gain = proceeds - cost;
+            if gain > Price::default() {
+                transaction.credit_entries.push(Entry {
+                    account: sale.gain_account,
+                    amount: Amount::Price(gain),
+                    memo: None,
+                });
+            } else if gain < Price::default() {
+                transaction.debit_entries.push(Entry {
+                    account: sale.gain_account,
+                    amount: Amount::Price(-gain),
+                    memo: None,
+                });
+            }
+        }
     }
 
     pub fn parse_directive(
@@ -70,27 +528,464 @@ impl Executor {
     ) -> Result<()> {
         let directive = directive.as_ref();
 
-        if let Some(date) = directive.strip_prefix(".date ") {
-            self.date = ExecutorDate::Fixed(Date::from(date));
+        let Some((name, args)) = split_directive(directive) else {
+            bail!("'{}' is not a directive", directive);
+        };
+
+        let Some(d) = self.directives.get(name).cloned() else {
+            bail!("Unknown directive '.{}'", name);
+        };
+
+        (d.handler)(self, args).map_err(|e| d.with_usage(e))
+    }
+
+    /// Resolves a full account name, or an account token as on the credit side
+    pub fn resolve_account(&self, name: &str) -> Result<String> {
+        if name.contains(':') {
+            return Ok(name.to_owned());
+        }
+
+        let mut factory = TransactionFactory::default();
+        match tokmap_dispatch!(tm, &self.token_mapper, factory.resolve_credit_account(tm, name)) {
+            Some(account) => Ok(account),
+            None => bail!("Account token '{}' can not be resolved", name),
         }
+    }
+
+    /// Reads `ACCOUNT AMOUNT` lines, where ACCOUNT is a full account name or
+    /// an account token. Shares are given as `ACCOUNT COST/SHARES`, held as a lot
+    /// bought on the current date. Empty lines and lines starting with `#` are ignored
+    pub fn load_opening_balances(&mut self, input: impl std::io::BufRead) -> Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let shares = line
+                .rsplit_once(char::is_whitespace)
+                .and_then(|(account, amount)| Some((account, amount.split_once('/')?)));
+            if let Some((account, (cost, shares))) = shares {
+                let (cost, shares): (Price, Price) = (cost.parse()?, shares.parse()?);
+                let account = self.resolve_account(account.trim())?;
+                match &mut self.ledger {
+                    Some(ledger) => ledger.open(&account, Commodity::Shares, shares),
+                    None => bail!("Ledger is disabled"),
+                }
+                let date = self.date.get_date();
+                self.opening_lots.add(&account, Lot { date, shares, cost });
+                continue;
+            }
 
-        if let Some(num) = directive.strip_prefix(".num ") {
-            self.num_base = num.parse()?;
+            let (account, amount) = split_account_amount(line)?;
+            let account = self.resolve_account(account)?;
+            match &mut self.ledger {
+                Some(ledger) => ledger.open(&account, Commodity::Currency, amount),
+                None => bail!("Ledger is disabled"),
+            }
         }
 
         Ok(())
     }
 
+    /// Checks the balance of an account at the current date, debits are positive
+    pub fn assert_balance(&self, name: &str, expected: Price) -> Result<()> {
+        let Some(ledger) = &self.ledger else {
+            bail!("Ledger is disabled");
+        };
+
+        let account = self.resolve_account(name)?;
+        let balance = ledger.balance(&account, self.date.get_date());
+        let balance = balance.get(&Commodity::Currency).copied().unwrap_or_default();
+
+        if balance != expected {
+            bail!(
+                "Balance of '{}' is {}, expected {}, difference {}",
+                account,
+                balance,
+                expected,
+                expected - balance
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Balances and postings of the transactions built so far, if tracked
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// Tracks balances of the journal, needed by `.balance`, opening balances,
+    /// statements and interest
+    pub fn enable_ledger(&mut self) {
+        if self.ledger.is_none() {
+            self.ledger = Some(Ledger::default());
+            self.rebuild_ledger();
+        }
+    }
+
+    /// Stops tracking balances, `.balance` is unavailable afterwards
+    pub fn disable_ledger(&mut self) {
+        self.ledger = None;
+    }
+
+    /// Returns the parser and directive state as they would be after the directive,
+    /// without changing the executor. Only directives changing the directive state,
+    /// variables or macros can be previewed
+    pub fn preview_directive(&self, directive: impl AsRef<str>) -> Result<DirectivePreview> {
+        let directive = directive.as_ref();
+        let Some((name, args)) = split_directive(directive) else {
+            bail!("'{}' is not a directive", directive);
+        };
+        if !["date", "num", "status", "set", "macro"].contains(&name) {
+            bail!("Directive '.{}' can not be previewed", name);
+        }
+        let Some(d) = self.directives.get(name) else {
+            bail!("Unknown directive '.{}'", name);
+        };
+
+        let mut preview = DirectivePreview {
+            parser: self.parser.clone(),
+            state: self.directive_state(),
+        };
+        let mut apply = || -> Result<()> {
+            match name {
+                "date" => preview.state.date = ExecutorDate::Fixed(args.parse()?),
+                "num" => preview.state.num_base = args.parse()?,
+                "status" => preview.state.status = args.parse()?,
+                "set" => {
+                    let (name, value) = split_assignment(args, "NAME=PRICE")?;
+                    define_variable(&mut preview.parser, name, value.parse()?)?;
+                }
+                _ => {
+                    let (name, template) = split_assignment(args, "NAME = EXPR")?;
+                    define_macro(&mut preview.parser, name, template)?;
+                }
+            }
+
+            Ok(())
+        };
+        apply().map_err(|e| d.with_usage(e))?;
+
+        Ok(preview)
+    }
+
+    pub fn register_directive(&mut self, directive: Directive) {
+        self.directives.register(directive);
+    }
+
+    pub fn list_directives(&self) -> impl Iterator<Item = &DirectiveInfo> {
+        self.directives.iter()
+    }
+
+    /// State set by the builtin directives, for frontends to scope it
+    pub fn directive_state(&self) -> DirectiveState {
+        DirectiveState {
+            date: self.date.clone(),
+            num_base: self.num_base,
+            status: self.status,
+        }
+    }
+
+    pub fn restore_directive_state(&mut self, state: DirectiveState) {
+        self.date = state.date;
+        self.num_base = state.num_base;
+        self.status = state.status;
+    }
+
+    /// Adds a recurring transaction, its expression is checked as of now
+    pub fn add_schedule(&mut self, schedule: Schedule) -> Result<()> {
+        self.preview(&schedule.expr)
+            .map_err(|e| err!("{}", e.to_string().trim_end()))?;
+
+        if !self.schedules.contains(&schedule) {
+            self.schedules.push(schedule);
+        }
+
+        Ok(())
+    }
+
+    pub fn schedules(&self) -> &[Schedule] {
+        &self.schedules
+    }
+
+    /// Occurrences of all schedules in `from..=to`, ordered by date
+    pub fn due_schedules(&self, from: Date, to: Date) -> Vec<(Date, &Schedule)> {
+        let mut due: Vec<(Date, &Schedule)> = self
+            .schedules
+            .iter()
+            .flat_map(|s| s.due_dates(from, to).into_iter().map(move |d| (d, s)))
+            .collect();
+        due.sort_by_key(|(date, _)| *date);
+
+        due
+    }
+
+    pub fn statement_cycles(&self) -> &[StatementCycle] {
+        &self.statement_cycles
+    }
+
+    /// Statement days of all cards in `from..=to`, ordered by date
+    pub fn due_statements(&self, from: Date, to: Date) -> Vec<(Date, &StatementCycle)> {
+        let mut due = Vec::new();
+        let mut date = from;
+
+        while date <= to {
+            for cycle in self.statement_cycles.iter() {
+                if date == date.with_day(cycle.statement_day) {
+                    due.push((date, cycle));
+                }
+            }
+            date = date.succ();
+        }
+
+        due
+    }
+
+    /// Builds the transfer of the unbilled balance at the end of `date` to the
+    /// billed account, or nothing if the balance is zero
+    pub fn build_statement(
+        &self,
+        cycle: &StatementCycle,
+        date: Date,
+    ) -> Result<Option<Transaction>> {
+        let Some(ledger) = &self.ledger else {
+            bail!("Ledger is disabled");
+        };
+
+        let balance = ledger.balance(&cycle.unbilled_account, date);
+        let balance = balance.get(&Commodity::Currency).copied().unwrap_or_default();
+        if balance == Price::default() {
+            return Ok(None);
+        }
+
+        let entry = |account: &String, amount: Price| Entry {
+            account: account.clone(),
+            amount: Amount::Price(amount),
+            memo: None,
+        };
+        // A negative balance is owed, and moved by debiting the unbilled account
+        let (debit, credit) = if balance.as_raw() < 0 {
+            (&cycle.unbilled_account, &cycle.billed_account)
+        } else {
+            (&cycle.billed_account, &cycle.unbilled_account)
+        };
+        let amount = if balance.as_raw() < 0 { -balance } else { balance };
+
+        Ok(Some(Transaction {
+            date,
+            num_base: self.num_base,
+            status: self.status,
+            description: Some(format!("{} 账单 {}", cycle.token, date)),
+            debit_entries: vec![entry(debit, amount)],
+            credit_entries: vec![entry(credit, amount)],
+            ..Default::default()
+        }))
+    }
+
+    pub fn interest_terms(&self) -> &[InterestTerms] {
+        &self.interest_terms
+    }
+
+    /// Builds the interest of `terms` accrued in `from..to` on the running
+    /// balance of its account, dated `to`, or nothing if the interest is zero
+    pub fn build_interest(
+        &self,
+        terms: &InterestTerms,
+        from: Date,
+        to: Date,
+    ) -> Result<Option<Transaction>> {
+        let Some(ledger) = &self.ledger else {
+            bail!("Ledger is disabled");
+        };
+
+        let account = self.resolve_account(&terms.token)?;
+        let amount = interest::accrue(terms, from, to, |date| {
+            let balance = ledger.balance(&account, date);
+            balance.get(&Commodity::Currency).copied().unwrap_or_default()
+        });
+        if amount == Price::default() {
+            return Ok(None);
+        }
+
+        let ctx = InterestContext {
+            token: terms.token.clone(),
+            amount: if amount.as_raw() < 0 { -amount } else { amount },
+            loan: amount.as_raw() < 0,
+        };
+        let mut factory = TransactionFactory::default();
+        let counterpart =
+            tokmap_dispatch!(tm, &self.token_mapper, factory.map_interest(tm, &ctx));
+
+        let entry = |account: &String| Entry {
+            account: account.clone(),
+            amount: Amount::Price(ctx.amount),
+            memo: None,
+        };
+        // Interest on a loan is an expense adding to what is owed
+        let (debit, credit) = if ctx.loan {
+            (&counterpart, &account)
+        } else {
+            (&account, &counterpart)
+        };
+
+        Ok(Some(Transaction {
+            date: to,
+            num_base: self.num_base,
+            status: self.status,
+            description: Some(format!("{} 利息 {} - {}", terms.token, from, to)),
+            debit_entries: vec![entry(debit)],
+            credit_entries: vec![entry(credit)],
+            ..Default::default()
+        }))
+    }
+
+    /// Builds and commits the interest in `from..to` of every account with
+    /// [`InterestTerms`] from the token mapper. Fails if any of them has
+    /// interest accrued in the period already
+    pub fn accrue_interest(&mut self, from: Date, to: Date) -> Result<Vec<Transaction>> {
+        if from >= to {
+            bail!("Interest period from {} to {} is empty", from, to);
+        }
+
+        for terms in self.interest_terms.iter() {
+            if let Some((_, f, t)) = self
+                .accrued_interest
+                .iter()
+                .find(|(token, f, t)| *token == terms.token && from < *t && *f < to)
+            {
+                bail!("Interest of '{}' is accrued from {} to {} already", terms.token, f, t);
+            }
+        }
+
+        let mut transactions = Vec::new();
+        for terms in self.interest_terms.clone().iter() {
+            if let Some(transaction) = self.build_interest(terms, from, to)? {
+                let transaction = self.commit_transaction(transaction);
+                if let Some(id) = transaction.id.clone() {
+                    self.interest_periods.insert(id, (terms.token.clone(), from, to));
+                }
+                transactions.push(transaction);
+            }
+            self.mark_interest_accrued(&terms.token, from, to);
+        }
+
+        Ok(transactions)
+    }
+
+    /// Interest periods `from..to` accrued per account token, see
+    /// [`crate::schedule::ScheduleState`] to remember them across sessions
+    pub fn accrued_interest(&self) -> &[(String, Date, Date)] {
+        &self.accrued_interest
+    }
+
+    /// Records interest accrued in `from..to`, e.g. in a previous session
+    pub fn mark_interest_accrued(&mut self, token: &str, from: Date, to: Date) {
+        let period = (token.to_owned(), from, to);
+        if !self.accrued_interest.contains(&period) {
+            self.accrued_interest.push(period);
+        }
+    }
+
+    /// Sets the value of `$name` in prices, variables are not scoped
+    pub fn set_variable(&mut self, name: &str, value: Price) -> Result<()> {
+        define_variable(&mut self.parser, name, value)
+    }
+
+    /// Defines a macro expanding a transaction beginning with `name`, see [`Parser::set_macro`]
+    pub fn set_macro(&mut self, name: &str, template: &str) -> Result<()> {
+        define_macro(&mut self.parser, name, template)
+    }
+
+    pub fn set_date(&mut self, date: Date) {
+        self.date = ExecutorDate::Fixed(date);
+    }
+
+    /// Date of the following transactions
+    pub fn get_date(&self) -> Date {
+        self.date.get_date()
+    }
+
+    pub fn set_num_base(&mut self, num_base: u32) {
+        self.num_base = num_base;
+    }
+
+    pub fn set_status(&mut self, status: TransactionStatus) {
+        self.status = status;
+    }
+
     pub fn enable_realtime_date(&mut self) {
         self.date = ExecutorDate::Realtime;
     }
 
+    /// Posts the remainder of unbalanced transactions to the fallback account explicitly
+    pub fn enable_imbalance_posting(&mut self) {
+        self.post_imbalance = true;
+    }
+
+    /// Merges entries with the same account on the same side into one split
+    pub fn enable_entry_merging(&mut self) {
+        self.merge_entries = true;
+    }
+
     pub fn get_tokmap_version(&self) -> &'static str {
         tokmap_dispatch!(tm, &self.token_mapper, tm.get_version())
     }
 }
 
-#[derive(Debug)]
+fn define_variable(parser: &mut Parser, name: &str, value: Price) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with(|ch: char| ch.is_ascii_digit())
+        || !name.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+    {
+        bail!("Invalid variable name '{}'", name);
+    }
+    if value.as_raw() < 0 {
+        bail!("Variable '{}' must not be negative", name);
+    }
+
+    parser.set_variable(name, value);
+    Ok(())
+}
+
+fn define_macro(parser: &mut Parser, name: &str, template: &str) -> Result<()> {
+    if name.is_empty() || name.contains(|ch: char| ch.is_whitespace() || ch == '|' || ch == '｜') {
+        bail!("Invalid macro name '{}'", name);
+    }
+    if !parser.set_macro(name, template) {
+        bail!("Macro name '{}' is already an account or hint token", name);
+    }
+
+    Ok(())
+}
+
+/// Splits `ACCOUNT AMOUNT` at the last whitespace, the account may contain spaces
+pub(crate) fn split_account_amount(s: &str) -> Result<(&str, Price)> {
+    match s.trim().rsplit_once(char::is_whitespace) {
+        Some((account, amount)) => Ok((account.trim(), amount.parse()?)),
+        None => bail!("Expected 'ACCOUNT AMOUNT', but got '{}'", s),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectiveState {
+    date: ExecutorDate,
+    num_base: u32,
+    status: TransactionStatus,
+}
+
+/// The parser and directive state as they would be after a directive, see
+/// [`Executor::preview_directive`]
+#[derive(Debug, Clone)]
+pub struct DirectivePreview {
+    pub parser: Parser,
+    pub state: DirectiveState,
+}
+
+#[derive(Debug, Clone)]
 pub enum ExecutorDate {
     Fixed(Date),
     Realtime,
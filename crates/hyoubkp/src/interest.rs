@@ -0,0 +1,38 @@
+use hyoubkp_base::date::Date;
+use hyoubkp_base::price::Price;
+use hyoubkp_base::tokmap::{Compounding, InterestTerms};
+
+/// Interest of `terms` on the balance at the end of each day in `from..to`,
+/// negative on a negative balance. Accrued interest earns interest itself
+/// according to the compounding of `terms`, and is rounded to cents once
+pub fn accrue(
+    terms: &InterestTerms,
+    from: Date,
+    to: Date,
+    balance: impl Fn(Date) -> Price,
+) -> Price {
+    // Cents per cent of the balance, for the rate in percent
+    let rate = terms.rate.as_raw() as f64 / 10000.0;
+    let mut accrued = 0.0;
+    let mut compounded = 0.0;
+    let mut date = from;
+
+    while date < to {
+        let base = balance(date).as_raw() as f64 + compounded;
+        let interest = base * rate * terms.day_count.year_fraction(date);
+        accrued += interest;
+
+        match terms.compounding {
+            Compounding::Simple => {}
+            Compounding::Daily => compounded += interest,
+            Compounding::Monthly => {
+                if date.day() == date.last_day_of_month() {
+                    compounded = accrued;
+                }
+            }
+        }
+        date = date.succ();
+    }
+
+    Price::new_unchecked(0, accrued.round() as i32)
+}
@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, HashMap};
+
+use hyoubkp_base::date::Date;
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{Commodity, Transaction};
+
+/// An entry of a transaction as seen from its account, debits are positive
+#[derive(Debug, Clone)]
+pub struct Posting {
+    pub date: Date,
+    pub commodity: Commodity,
+    pub amount: Price,
+    pub memo: Option<String>,
+    pub orig_expr: Option<String>,
+}
+
+/// Balances and postings per full account name
+#[derive(Debug, Default, Clone)]
+pub struct Ledger {
+    opening: HashMap<String, BTreeMap<Commodity, Price>>,
+    postings: HashMap<String, Vec<Posting>>,
+}
+
+impl Ledger {
+    pub fn post(&mut self, transaction: &Transaction) {
+        for (entry, sign) in transaction
+            .debit_entries
+            .iter()
+            .map(|e| (e, 1))
+            .chain(transaction.credit_entries.iter().map(|e| (e, -1)))
+        {
+            let quantity = entry.amount.quantity();
+            self.postings
+                .entry(entry.account.clone())
+                .or_default()
+                .push(Posting {
+                    date: transaction.date,
+                    commodity: entry.amount.commodity(),
+                    amount: if sign > 0 { quantity } else { -quantity },
+                    memo: entry.memo.clone(),
+                    orig_expr: transaction.orig_expr.clone(),
+                });
+        }
+    }
+
+    /// Forgets all postings, keeping the opening balances
+    pub fn clear_postings(&mut self) {
+        self.postings.clear();
+    }
+
+    /// Adds to the balance before any posting
+    pub fn open(&mut self, account: &str, commodity: Commodity, amount: Price) {
+        *self
+            .opening
+            .entry(account.to_owned())
+            .or_default()
+            .entry(commodity)
+            .or_default() += amount;
+    }
+
+    /// Balance per commodity, including the postings on `date`
+    pub fn balance(&self, account: &str, date: Date) -> BTreeMap<Commodity, Price> {
+        let mut balance = self.opening.get(account).cloned().unwrap_or_default();
+
+        for posting in self.postings(account).iter().filter(|p| p.date <= date) {
+            *balance.entry(posting.commodity).or_default() += posting.amount;
+        }
+
+        balance
+    }
+
+    /// Postings of an account in the order they were made
+    pub fn postings(&self, account: &str) -> &[Posting] {
+        self.postings.get(account).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        let mut accounts: Vec<&str> = self
+            .opening
+            .keys()
+            .chain(self.postings.keys().filter(|a| !self.opening.contains_key(*a)))
+            .map(String::as_str)
+            .collect();
+        accounts.sort();
+        accounts.into_iter()
+    }
+}
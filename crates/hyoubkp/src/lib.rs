@@ -1,6 +1,12 @@
 pub mod datagen;
+pub mod directive;
 pub mod executor;
+pub mod interest;
+pub mod ledger;
+pub mod lots;
 pub mod parser;
+pub mod reimbursement;
+pub mod schedule;
 pub mod tokmap;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
\ No newline at end of file
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use hyoubkp_base::date::Date;
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{Amount, Transaction};
+
+/// Shares bought together, or the part of them left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lot {
+    pub date: Date,
+    pub shares: Price,
+    pub cost: Price,
+}
+
+impl std::fmt::Display for Lot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} / {}", self.date, self.shares, self.cost)
+    }
+}
+
+/// Lots held by each account, oldest first
+#[derive(Debug, Default, Clone)]
+pub struct Lots {
+    lots: HashMap<String, Vec<Lot>>,
+}
+
+impl Lots {
+    /// Opens a lot for each debit of shares, and reduces the lots named by
+    /// the credits of [`Amount::Lot`]
+    pub fn post(&mut self, transaction: &Transaction) {
+        self.buy(transaction);
+
+        for entry in transaction.credit_entries.iter() {
+            if let Amount::Lot(shares, cost, date) = entry.amount {
+                self.reduce(&entry.account, shares, cost, date);
+            }
+        }
+    }
+
+    /// Opens a lot for each debit of shares
+    pub fn buy(&mut self, transaction: &Transaction) {
+        for entry in transaction.debit_entries.iter() {
+            if let Amount::Shares(shares, cost) = entry.amount {
+                self.add(
+                    &entry.account,
+                    Lot {
+                        date: transaction.date,
+                        shares,
+                        cost,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn add(&mut self, account: &str, lot: Lot) {
+        self.lots.entry(account.to_owned()).or_default().push(lot);
+    }
+
+    /// Takes `shares` costing `cost` out of the lots of `account` bought on `date`
+    fn reduce(&mut self, account: &str, mut shares: Price, mut cost: Price, date: Date) {
+        let Some(lots) = self.lots.get_mut(account) else {
+            return;
+        };
+
+        lots.retain_mut(|lot| {
+            if lot.date != date || shares == Price::default() {
+                return true;
+            }
+            if lot.shares > shares {
+                lot.shares = lot.shares - shares;
+                lot.cost = lot.cost - cost;
+                shares = Price::default();
+                return true;
+            }
+            shares = shares - lot.shares;
+            cost = cost - lot.cost;
+            false
+        });
+    }
+
+    pub fn lots(&self, account: &str) -> &[Lot] {
+        self.lots
+            .get(account)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Picks the lots to sell `shares` of `account` from, those bought on
+    /// `prefer` first and then the oldest. Returns the lots with the shares
+    /// taken and their cost, and the shares not held
+    pub fn select(&self, account: &str, shares: Price, prefer: Option<Date>) -> (Vec<Lot>, Price) {
+        let lots = self.lots(account);
+        let mut left = shares;
+        let mut selected = Vec::new();
+
+        let preferred = lots.iter().filter(|lot| Some(lot.date) == prefer);
+        let others = lots.iter().filter(|lot| Some(lot.date) != prefer);
+
+        for lot in preferred.chain(others) {
+            if left == Price::default() {
+                break;
+            }
+
+            let taken = left.min(lot.shares);
+            let cost = if taken == lot.shares {
+                lot.cost
+            } else {
+                lot.cost.prorate(taken, lot.shares)
+            };
+
+            selected.push(Lot {
+                date: lot.date,
+                shares: taken,
+                cost,
+            });
+            left = left - taken;
+        }
+
+        (selected, left)
+    }
+
+    /// Same as [`Lots::select`], and takes the lots selected out of `account`
+    pub fn sell(
+        &mut self,
+        account: &str,
+        shares: Price,
+        prefer: Option<Date>,
+    ) -> (Vec<Lot>, Price) {
+        let (selected, missing) = self.select(account, shares, prefer);
+
+        for lot in selected.iter() {
+            self.reduce(account, lot.shares, lot.cost, lot.date);
+        }
+
+        (selected, missing)
+    }
+}
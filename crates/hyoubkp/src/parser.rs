@@ -1,48 +1,8 @@
-use std::fmt::Debug;
-use std::ops::Deref;
-use std::ops::DerefMut;
+use std::collections::HashMap;
 
 use hyoubkp_base::expr::*;
 use hyoubkp_base::price::Price;
-
-#[derive(Clone)]
-pub struct UnsafeNodeRef<T> {
-    node: *mut T,
-}
-
-impl<T> From<&mut T> for UnsafeNodeRef<T> {
-    fn from(value: &mut T) -> Self {
-        Self {
-            node: value as *mut T,
-        }
-    }
-}
-
-impl<T> Deref for UnsafeNodeRef<T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.node }
-    }
-}
-
-impl<T> DerefMut for UnsafeNodeRef<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.node }
-    }
-}
-
-impl<T> Debug for UnsafeNodeRef<T>
-where
-    T: Debug,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("UnsafeNodeRef")
-            .field("addr", &(self.node as usize))
-            .field("raw", &*self)
-            .finish()
-    }
-}
+use hyoubkp_base::transaction::TransactionStatus;
 
 #[derive(Default, Clone, Debug)]
 pub struct Node {
@@ -56,6 +16,7 @@ pub enum NodeKind {
     Staging,
     AccountToken,
     HintToken,
+    MacroToken,
 }
 
 impl Default for NodeKind {
@@ -65,10 +26,6 @@ impl Default for NodeKind {
 }
 
 impl Node {
-    pub fn unsafe_ref(&mut self) -> UnsafeNodeRef<Self> {
-        UnsafeNodeRef::from(self)
-    }
-
     pub fn find(&self, ch: char) -> Option<&Node> {
         for i in self.next.iter() {
             if i.ch == ch {
@@ -89,58 +46,78 @@ impl Node {
         None
     }
 
-    pub fn push_node(&mut self, n: Node) -> UnsafeNodeRef<Self> {
+    pub fn push_node(&mut self, n: Node) -> &mut Self {
         self.next.push(n);
-        self.next.last_mut().unwrap().into()
+        self.next.last_mut().unwrap()
     }
 
     pub fn feed(&mut self, s: &str, kind: NodeKind) {
-        let mut node = UnsafeNodeRef::from(self);
+        let mut node = self;
 
         for ch in s.chars() {
-            match node.find_mut(ch) {
-                Some(nn) => {
-                    node = UnsafeNodeRef::from(nn);
-                }
-                None => {
-                    node = node.push_node(Node {
-                        ch: ch,
-                        ..Default::default()
-                    });
-                }
-            }
+            node = match node.next.iter().position(|n| n.ch == ch) {
+                Some(i) => &mut node.next[i],
+                None => node.push_node(Node {
+                    ch: ch,
+                    ..Default::default()
+                }),
+            };
         }
 
         node.kind = kind;
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Clone, Debug)]
 pub struct Parser {
     tree: Node,
-    state: State,
+    variables: HashMap<String, Price>,
+    macros: HashMap<String, String>,
+    history: Vec<String>,
+}
+
+/// Per-line parsing state, which borrows the token tree of a [`Parser`]
+#[derive(Debug)]
+struct ParseContext<'a> {
+    tree: &'a Node,
+    variables: &'a HashMap<String, Price>,
+    macros: &'a HashMap<String, String>,
+    history: &'a [String],
+    state: State<'a>,
+    parsed: Vec<ParsedToken>,
+}
+
+/// Account and hint tokens and prices in the order parsed, prices with the
+/// range of their chars in the input line
+#[derive(Debug)]
+enum ParsedToken {
+    AccountOrHint(String),
+    Price(String, std::ops::Range<usize>),
 }
 
 #[derive(Default, Debug)]
-pub struct State {
-    estate: ExprState,
+pub struct State<'a> {
+    estate: ExprState<'a>,
     pstate: PriceState,
     pbitset: u8,
 
     staging_token: String,
+    /// Position of the first char of a price in `staging_token`
+    staging_pos: usize,
+    step_hint_allowed: bool,
     ch: char,
     pos: usize,
 }
 
-#[derive(Clone, Debug)]
-pub enum ExprState {
+#[derive(Clone, Copy, Debug)]
+pub enum ExprState<'a> {
     Swap,
-    ExprPartAccAndHint(UnsafeNodeRef<Node>),
+    ExprPartAccAndHint(&'a Node),
     ExprPartPrice,
-    // ExprComment,
+    ExprComment,
 }
 
-impl Default for ExprState {
+impl Default for ExprState<'_> {
     fn default() -> Self {
         Self::Swap
     }
@@ -155,10 +132,12 @@ pub enum PriceState {
     Shares = 0b1000,
     Multiple = 0b10000,
     Cashback = 0b100000,
+    Fee = 0b1000000,
+    Installment = 0b10000000,
 }
 
 pub static PRICE_STATE_REENTRANT_MASK: u8 = 0b100110;
-pub static PRICE_STATE_ALL_MASK: u8 = 0b111111;
+pub static PRICE_STATE_ALL_MASK: u8 = 0b11111111;
 
 impl Default for PriceState {
     fn default() -> Self {
@@ -184,18 +163,89 @@ impl Parser {
 
         Self {
             tree: tree,
-            ..Default::default()
+            variables: HashMap::new(),
+            macros: HashMap::new(),
+            history: Vec::new(),
         }
     }
 
+    /// Token tree of accounts, hints and macros, e.g. for completion
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    /// Defines a macro, which expands a transaction beginning with `name` to
+    /// `template`, with `$1`, `$2`... replaced by the following words.
+    /// Returns false if the name is already an account or hint token
+    pub fn set_macro(&mut self, name: impl Into<String>, template: impl Into<String>) -> bool {
+        let name = name.into();
+
+        let mut node = Some(&self.tree);
+        for ch in name.chars() {
+            node = node.and_then(|n| n.find(ch));
+        }
+        if let Some(Node {
+            kind: NodeKind::AccountToken | NodeKind::HintToken,
+            ..
+        }) = node
+        {
+            return false;
+        }
+
+        self.tree.feed(&name, NodeKind::MacroToken);
+        self.macros.insert(name, template.into());
+        true
+    }
+
+    pub fn macros(&self) -> &HashMap<String, String> {
+        &self.macros
+    }
+
+    /// Appends a committed transaction to the history used by `!!` and `!TOKEN`
+    pub fn push_history(&mut self, expr: impl Into<String>) {
+        self.history.push(expr.into());
+    }
+
+    pub fn pop_history(&mut self) -> Option<String> {
+        self.history.pop()
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Sets the value of `$name` in prices
+    pub fn set_variable(&mut self, name: impl Into<String>, value: Price) {
+        self.variables.insert(name.into(), value);
+    }
+
+    pub fn variables(&self) -> &HashMap<String, Price> {
+        &self.variables
+    }
+
+    /// Parses one input line, which holds one or more transactions separated by `|`
+    pub fn parse_expr(&self, expr_str: impl AsRef<str>) -> ParseResult<Vec<CompoundExpr>> {
+        ParseContext {
+            tree: &self.tree,
+            variables: &self.variables,
+            macros: &self.macros,
+            history: &self.history,
+            state: State::default(),
+            parsed: Vec::new(),
+        }
+        .parse_expr(expr_str.as_ref())
+    }
+}
+
+impl<'a> ParseContext<'a> {
     fn begin_acc_or_hint(&mut self, ch: Option<char>) -> bool {
-        self.state.estate = ExprState::ExprPartAccAndHint(self.tree.unsafe_ref());
+        self.state.estate = ExprState::ExprPartAccAndHint(self.tree);
 
         if let Some(ch) = ch {
-            match self.tree.find_mut(ch) {
+            match self.tree.find(ch) {
                 Some(nn) => {
                     self.state.staging_token.push(ch);
-                    self.state.estate = ExprState::ExprPartAccAndHint(nn.unsafe_ref());
+                    self.state.estate = ExprState::ExprPartAccAndHint(nn);
                 }
                 None => return false,
             };
@@ -206,13 +256,18 @@ impl Parser {
 
     fn pop_acc_or_hint_token(
         &mut self,
-        node: UnsafeNodeRef<Node>,
+        node: &'a Node,
         out_expr: &mut Expr,
     ) -> ParseResult<()> {
         if self.state.staging_token.is_empty() {
             parse_fail!(self, "Account or hint is required",);
         }
 
+        if let NodeKind::AccountToken | NodeKind::HintToken = node.kind {
+            let token = self.state.staging_token.clone();
+            self.parsed.push(ParsedToken::AccountOrHint(token));
+        }
+
         match node.kind {
             NodeKind::Staging => parse_fail!(
                 self,
@@ -225,14 +280,415 @@ impl Parser {
             NodeKind::HintToken => out_expr
                 .hints
                 .push(std::mem::take(&mut self.state.staging_token)),
+            NodeKind::MacroToken => parse_fail!(
+                self,
+                "Macro '{}' must begin a transaction",
+                self.state.staging_token,
+            ),
         }
 
         self.state.staging_token.clear();
-        self.state.estate = ExprState::ExprPartAccAndHint(self.tree.unsafe_ref());
+        self.state.estate = ExprState::ExprPartAccAndHint(self.tree);
+
+        Ok(())
+    }
+
+    /// Returns the char length of the hint token starting at `chars`, if the longest
+    /// known token there is a hint
+    fn match_step_hint(&self, chars: &[char]) -> Option<usize> {
+        let mut node = self.tree;
+        let mut matched = None;
+
+        for (i, ch) in chars.iter().enumerate() {
+            match node.find(*ch) {
+                Some(nn) => node = nn,
+                None => break,
+            }
+
+            match node.kind {
+                NodeKind::Staging => (),
+                NodeKind::AccountToken | NodeKind::MacroToken => matched = None,
+                NodeKind::HintToken => matched = Some(i + 1),
+            }
+        }
+
+        matched
+    }
+
+    fn push_step_hint(&mut self, hint: HintToken, out_trans: &mut ExprTrans) -> ParseResult<()> {
+        let hints = match self.state.pstate {
+            PriceState::Credit | PriceState::Reward => {
+                out_trans.price_credit_chain.last_mut().map(|s| &mut s.hints)
+            }
+            PriceState::Cashback => out_trans.cash_backs.last_mut().map(|s| &mut s.hints),
+            _ => None,
+        };
+
+        self.parsed.push(ParsedToken::AccountOrHint(hint.clone()));
+        match hints {
+            Some(hints) => hints.push(hint),
+            None => parse_fail!(self, "Hint '{}' must follow a reward or cashback", hint),
+        }
 
         Ok(())
     }
 
+    /// Expands the history reference and the macro beginning each transaction of
+    /// `orig`. Returns the chars to parse ending with NUL, the position in `orig` of
+    /// each of them, and the end position and source after history expansion of
+    /// each segment between separators, which a macro may expand to several transactions
+    fn expand(&self, orig: &[char]) -> ParseResult<(Vec<char>, Vec<usize>, Sources)> {
+        let mut chars = Vec::with_capacity(orig.len() + 1);
+        let mut origins = Vec::with_capacity(orig.len() + 1);
+        let mut sources = Vec::new();
+        let mut begin = 0;
+
+        for end in 0..=orig.len() {
+            if end < orig.len() && !matches!(orig[end], '|' | '｜') {
+                continue;
+            }
+
+            let (segment, segment_origins) = self.expand_history(&orig[begin..end], begin)?;
+            sources.push((end, segment.iter().collect::<String>().trim().to_owned()));
+            self.expand_macro(&segment, &segment_origins, &mut chars, &mut origins)?;
+
+            // The separator, or the trailing NUL
+            chars.push(orig.get(end).copied().unwrap_or('\0'));
+            origins.push(end);
+            begin = end + 1;
+        }
+
+        Ok((chars, origins, sources))
+    }
+
+    /// Returns the char length of the leading whitespace and status flag of `segment`
+    fn skip_status_flag(segment: &[char]) -> usize {
+        let start = segment.iter().take_while(|ch| ch.is_whitespace()).count();
+        let flag_len = segment[start..]
+            .iter()
+            .take_while(|ch| !ch.is_whitespace())
+            .count();
+        let flag: String = segment[start..start + flag_len].iter().collect();
+
+        if TransactionStatus::from_flag(&flag).is_some()
+            && segment
+                .get(start + flag_len)
+                .is_some_and(|ch| ch.is_whitespace())
+        {
+            start
+                + flag_len
+                + segment[start + flag_len..]
+                    .iter()
+                    .take_while(|ch| ch.is_whitespace())
+                    .count()
+        } else {
+            start
+        }
+    }
+
+    /// Replaces a transaction written as `!!` with the last committed one, or
+    /// `!TOKEN [PRICE]` with the last one using TOKEN, with its first price after
+    /// TOKEN replaced. A `!` followed by whitespace is a status flag instead
+    fn expand_history(
+        &self,
+        segment: &[char],
+        offset: usize,
+    ) -> ParseResult<(Vec<char>, Vec<usize>)> {
+        let start = Self::skip_status_flag(segment);
+
+        let words: Vec<String> = segment[start..]
+            .iter()
+            .collect::<String>()
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        let is_bang = |ch: char| ch == '!' || ch == '！';
+        let reference = match words.first() {
+            Some(word) if word.starts_with(is_bang) && word.chars().count() > 1 => word,
+            _ => return Ok((segment.to_vec(), (offset..offset + segment.len()).collect())),
+        };
+        let pos = offset + start;
+        let history_error = |msg: String| ParseError {
+            msg,
+            ch: segment[start],
+            pos,
+            len: segment.len() - start,
+        };
+
+        let token: String = reference.chars().skip(1).collect();
+        let expanded = if token.chars().all(is_bang) {
+            if words.len() > 1 || token.chars().count() > 1 {
+                return Err(history_error(String::from("'!!' takes no argument")));
+            }
+
+            match self.history.last() {
+                Some(expr) => expr.clone(),
+                None => return Err(history_error(String::from("History is empty"))),
+            }
+        } else {
+            if words.len() > 2 {
+                return Err(history_error(format!("'!{}' takes at most a price", token)));
+            }
+
+            let found = self.history.iter().rev().find_map(|expr| {
+                let parsed = self.parse_history(expr)?;
+                let i = parsed.iter().position(
+                    |t| matches!(t, ParsedToken::AccountOrHint(t) if *t == token),
+                )?;
+                Some((expr, parsed, i))
+            });
+            let Some((expr, parsed, i)) = found else {
+                return Err(history_error(format!(
+                    "No expression with '{}' in history",
+                    token
+                )));
+            };
+
+            match words.get(1) {
+                Some(price) => {
+                    let expr: Vec<char> = expr.chars().collect();
+                    // A price from the template of a macro is not in the expression
+                    let range = parsed[i + 1..]
+                        .iter()
+                        .find_map(|t| match t {
+                            ParsedToken::Price(text, range) => Some((text, range.clone())),
+                            _ => None,
+                        })
+                        .filter(|(text, range)| {
+                            let written: String = expr[range.clone()].iter().collect();
+                            written == **text || written.starts_with('$')
+                        });
+
+                    let Some((_, range)) = range else {
+                        return Err(history_error(format!(
+                            "No price follows '{}' in '{}'",
+                            token,
+                            expr.iter().collect::<String>()
+                        )));
+                    };
+
+                    expr[..range.start]
+                        .iter()
+                        .copied()
+                        .chain(price.chars())
+                        .chain(expr[range.end..].iter().copied())
+                        .collect()
+                }
+                None => expr.clone(),
+            }
+        };
+
+        let expanded: Vec<char> = segment[..start]
+            .iter()
+            .copied()
+            .chain(expanded.chars())
+            .collect();
+        let origins = (offset..pos)
+            .chain(std::iter::repeat(pos))
+            .take(expanded.len())
+            .collect();
+
+        Ok((expanded, origins))
+    }
+
+    /// Account and hint tokens and prices of a history entry, None if it fails to parse
+    fn parse_history(&self, expr: &str) -> Option<Vec<ParsedToken>> {
+        let mut ctx = ParseContext {
+            tree: self.tree,
+            variables: self.variables,
+            macros: self.macros,
+            history: &[],
+            state: State::default(),
+            parsed: Vec::new(),
+        };
+        ctx.parse_expr(expr).ok()?;
+
+        Some(ctx.parsed)
+    }
+
+    /// Expands the macro beginning `segment`, appending the result to `chars` and
+    /// the position in the input line of each char to `origins`
+    fn expand_macro(
+        &self,
+        segment: &[char],
+        segment_origins: &[usize],
+        chars: &mut Vec<char>,
+        origins: &mut Vec<usize>,
+    ) -> ParseResult<()> {
+        let start = Self::skip_status_flag(segment);
+
+        let mut node = self.tree;
+        let mut name_len = None;
+        for (i, ch) in segment[start..].iter().enumerate() {
+            match node.find(*ch) {
+                Some(nn) => node = nn,
+                None => break,
+            }
+            if let NodeKind::MacroToken = node.kind {
+                name_len = Some(i + 1);
+            }
+        }
+
+        let name_len = name_len.filter(|len| {
+            segment
+                .get(start + len)
+                .is_none_or(|ch| ch.is_whitespace())
+        });
+
+        let Some(name_len) = name_len else {
+            chars.extend(segment);
+            origins.extend(segment_origins);
+            return Ok(());
+        };
+
+        let name: String = segment[start..start + name_len].iter().collect();
+
+        chars.extend(&segment[..start]);
+        origins.extend(&segment_origins[..start]);
+
+        // Words after the name, as ranges of `segment`
+        let mut args: Vec<(usize, usize)> = Vec::new();
+        for (i, ch) in segment.iter().enumerate().skip(start + name_len) {
+            if ch.is_whitespace() {
+                continue;
+            }
+            match args.last_mut() {
+                Some((_, e)) if *e == i => *e = i + 1,
+                _ => args.push((i, i + 1)),
+            }
+        }
+
+        let template: Vec<char> = self.macros[&name].chars().collect();
+        let mut used = 0;
+        let mut i = 0;
+        while i < template.len() {
+            let n = template[i + 1..]
+                .iter()
+                .take_while(|ch| ch.is_ascii_digit())
+                .count();
+
+            if template[i] != '$' || n == 0 {
+                chars.push(template[i]);
+                origins.push(segment_origins[start]);
+                i += 1;
+                continue;
+            }
+
+            let index: usize = template[i + 1..i + 1 + n]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .unwrap_or_default();
+            let Some(&(b, e)) = index.checked_sub(1).and_then(|i| args.get(i)) else {
+                return Err(ParseError {
+                    msg: format!("Macro '{}' requires argument ${}", name, index),
+                    ch: segment[start],
+                    pos: segment_origins[start],
+                    len: name_len,
+                });
+            };
+
+            chars.extend(&segment[b..e]);
+            origins.extend(&segment_origins[b..e]);
+            used = used.max(index);
+            i += 1 + n;
+        }
+
+        if let Some(&(b, e)) = args.get(used) {
+            return Err(ParseError {
+                msg: format!("Macro '{}' takes {} argument(s)", name, used),
+                ch: segment[b],
+                pos: segment_origins[b],
+                len: e - b,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value and char length of the `$name` or `${name}` starting at
+    /// `chars`. Without braces, the longest defined name is taken, like `$a` in `$ax2`
+    fn match_variable(&self, chars: &[char]) -> ParseResult<(Price, usize)> {
+        let (name, len) = if chars.get(1) == Some(&'{') {
+            match chars.iter().position(|&ch| ch == '}') {
+                Some(end) => (&chars[2..end], end + 1),
+                None => {
+                    return Err(ParseError {
+                        len: chars.len() - 1,
+                        ..parse_error!(self, "Variable name is not closed")
+                    })
+                }
+            }
+        } else {
+            let n = chars[1..]
+                .iter()
+                .take_while(|ch| ch.is_alphanumeric() || **ch == '_')
+                .count();
+
+            for i in (1..=n).rev() {
+                let name: String = chars[1..1 + i].iter().collect();
+                if let Some(value) = self.variables.get(&name) {
+                    return Ok((*value, i + 1));
+                }
+            }
+
+            (&chars[1..1 + n], n + 1)
+        };
+
+        let name: String = name.iter().collect();
+        match self.variables.get(&name) {
+            Some(value) if !name.is_empty() => Ok((*value, len)),
+            _ => Err(ParseError {
+                len,
+                ..parse_error!(self, "Variable '${}' is not defined", name)
+            }),
+        }
+    }
+
+    /// Handles a `#tag` or `key:value` word starting at `chars`, returns its char length
+    fn match_tag_or_metadata(chars: &[char], out: &mut CompoundExpr) -> Option<usize> {
+        let len = chars
+            .iter()
+            .position(|ch| {
+                matches!(
+                    ch,
+                    ' ' | '\t' | '\0' | '|' | '｜' | '\'' | '‘' | '’' | '"' | '“' | '”'
+                )
+            })
+            .unwrap_or(chars.len());
+        let word: String = chars[..len].iter().collect();
+
+        if let Some(tag) = word.strip_prefix('#') {
+            if tag.is_empty() {
+                return None;
+            }
+
+            out.tags.push(tag.to_owned());
+        } else if let Some((key, value)) = word.split_once([':', '：']) {
+            if key.is_empty() || key.starts_with(|ch: char| ch.is_ascii_digit()) {
+                return None;
+            }
+
+            if key == "payee" {
+                out.payee = Some(value.to_owned());
+            } else {
+                out.metadata.push((key.to_owned(), value.to_owned()));
+            }
+        } else {
+            return None;
+        }
+
+        Some(len)
+    }
+
+    fn push_price_char(&mut self, ch: char) {
+        if self.state.staging_token.is_empty() {
+            self.state.staging_pos = self.state.pos;
+        }
+        self.state.staging_token.push(ch);
+    }
+
     fn pop_price_token(&mut self, out_trans: &mut ExprTrans) -> ParseResult<()> {
         let value = self.state.staging_token.parse::<Price>().map_err(|e| {
             parse_error!(
@@ -242,15 +698,19 @@ impl Parser {
                 e
             )
         })?;
+        self.parsed.push(ParsedToken::Price(
+            self.state.staging_token.clone(),
+            self.state.staging_pos..self.state.pos,
+        ));
 
         match self.state.pstate {
             PriceState::Debit => out_trans.price_debit = value,
             PriceState::Credit => out_trans
                 .price_credit_chain
-                .push(ExprCreditPrice::Credit(value)),
+                .push(ExprCreditPrice::Credit(value).into()),
             PriceState::Reward => out_trans
                 .price_credit_chain
-                .push(ExprCreditPrice::Reward(value)),
+                .push(ExprCreditPrice::Reward(value).into()),
             PriceState::Shares => out_trans.shares = Some(value),
             PriceState::Multiple => {
                 if value.fractional_part() != 0 {
@@ -263,10 +723,26 @@ impl Parser {
 
                 out_trans.multiple = value.integer_part() as u32;
             }
-            PriceState::Cashback => out_trans.cash_backs.push(value),
+            PriceState::Cashback => out_trans.cash_backs.push(value.into()),
+            PriceState::Fee => out_trans.fee = Some(value),
+            PriceState::Installment => {
+                if value.fractional_part() != 0 || value.integer_part() < 1 {
+                    parse_fail!(
+                        self,
+                        "Installment periods should be a positive integer, but currently is '{}'",
+                        self.state.staging_token
+                    );
+                }
+
+                out_trans.installments = Some(value.integer_part() as u32);
+            }
         };
 
         self.state.staging_token.clear();
+        self.state.step_hint_allowed = matches!(
+            self.state.pstate,
+            PriceState::Credit | PriceState::Reward | PriceState::Cashback
+        );
 
         Ok(())
     }
@@ -296,10 +772,35 @@ impl Parser {
         Ok(())
     }
 
-    pub fn parse_expr(&mut self, expr_str: impl AsRef<str>) -> ParseResult<CompoundExpr> {
-        let expr_str = expr_str.as_ref();
+    fn end_token(&mut self, expr: &mut Expr, trans: &mut ExprTrans) -> ParseResult<()> {
+        match self.state.estate {
+            ExprState::Swap | ExprState::ExprComment => (),
+            ExprState::ExprPartAccAndHint(node) => {
+                if !self.state.staging_token.is_empty() {
+                    self.pop_acc_or_hint_token(node, expr)?;
+                }
+            }
+            ExprState::ExprPartPrice => {
+                if !self.state.staging_token.is_empty() {
+                    self.pop_price_token(trans)?;
+                }
+                if !trans.is_empty() {
+                    expr.trans.push(std::mem::take(trans));
+                }
+
+                self.state.pbitset = 0;
+                self.state.step_hint_allowed = false;
+                self.change_pstate(PriceState::Debit)?;
+            }
+        }
+
+        Ok(())
+    }
 
+    fn parse_expr(&mut self, expr_str: &str) -> ParseResult<Vec<CompoundExpr>> {
+        let mut results = Vec::new();
         let mut result = CompoundExpr::default();
+        let mut memo: Option<String> = None;
 
         let mut expr = Expr::default();
         let mut trans = ExprTrans::default();
@@ -308,45 +809,138 @@ impl Parser {
 
         self.begin_acc_or_hint(None);
 
-        for (pos, ch) in expr_str.chars().chain("\0".chars()).enumerate() {
+        let orig_chars: Vec<char> = expr_str.chars().collect();
+        // Position in `orig_chars` of each char, macros and variables are expanded in `chars`
+        let (mut chars, mut origins, sources) = self.expand(&orig_chars)?;
+        let mut skip = 0;
+
+        for pos in 0.. {
+            if pos >= chars.len() {
+                break;
+            }
+
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+
+            let mut ch = chars[pos];
             self.state.ch = ch;
-            self.state.pos = pos;
+            self.state.pos = origins[pos];
+
+            if let ExprState::ExprComment = self.state.estate {
+                if !matches!(ch, '\0' | '|' | '｜') {
+                    result.comment.get_or_insert_with(String::new).push(ch);
+                    continue;
+                }
+            }
+
+            if let Some(m) = memo.as_mut() {
+                match ch {
+                    '"' | '“' | '”' => expr.memo = memo.take(),
+                    '\0' | '|' | '｜' => parse_fail!(self, "Memo is not closed"),
+                    _ => m.push(ch),
+                }
+                continue;
+            }
+
+            if result.status.is_none()
+                && result.exprs.is_empty()
+                && expr.is_empty()
+                && self.state.staging_token.is_empty()
+                && matches!(ch, '!' | '！' | '*')
+            {
+                let len = chars[pos..]
+                    .iter()
+                    .position(|ch| matches!(ch, ' ' | '\t' | '\0'))
+                    .unwrap_or(0);
+                let flag: String = chars[pos..pos + len].iter().collect();
+                if let Some(status) = TransactionStatus::from_flag(&flag) {
+                    result.status = Some(status);
+                    skip = len - 1;
+                    continue;
+                }
+            }
+
+            if let ExprState::ExprPartPrice = self.state.estate {
+                if self.state.staging_token.is_empty() && matches!(chars[pos - 1], ' ' | '\t') {
+                    if let Some(len) = Self::match_tag_or_metadata(&chars[pos..], &mut result) {
+                        skip = len - 1;
+                        continue;
+                    }
+                }
+            }
+
+            if ch == '$'
+                && matches!(
+                    self.state.estate,
+                    ExprState::ExprPartAccAndHint(_) | ExprState::ExprPartPrice
+                )
+            {
+                if let ExprState::ExprPartPrice = self.state.estate {
+                    if !self.state.staging_token.is_empty() {
+                        parse_fail!(self, "Variable must follow an operator");
+                    }
+                }
+
+                let (value, len) = self.match_variable(&chars[pos..])?;
+                let value: Vec<char> = value.to_string().chars().collect();
+
+                origins.splice(pos..pos + len, std::iter::repeat_n(origins[pos], value.len()));
+                chars.splice(pos..pos + len, value);
+
+                ch = chars[pos];
+                self.state.ch = ch;
+            }
 
             match ch {
-                ' ' | '\t' | '\0' => match self.state.estate.clone() {
-                    ExprState::Swap => (),
-                    ExprState::ExprPartAccAndHint(node) => {
-                        if !self.state.staging_token.is_empty() {
-                            self.pop_acc_or_hint_token(node, &mut expr)?;
-                        }
+                ' ' | '\t' => self.end_token(&mut expr, &mut trans)?,
+                '\0' | '|' | '｜' => {
+                    self.end_token(&mut expr, &mut trans)?;
+
+                    if !expr.is_empty() {
+                        result.exprs.push(std::mem::take(&mut expr));
                     }
-                    ExprState::ExprPartPrice => {
-                        if !self.state.staging_token.is_empty() {
-                            self.pop_price_token(&mut trans)?;
-                            expr.trans.push(std::mem::take(&mut trans));
-                        }
 
-                        self.state.pbitset = 0;
-                        self.change_pstate(PriceState::Debit)?;
+                    if let Some(comment) = result.comment.as_mut() {
+                        comment.truncate(comment.trim_end().len());
                     }
-                },
-                '\'' | '‘' | '’' => {
-                    if let Some(byte_index) = expr_str.char_indices().nth(pos + 1).map(|(i, _)| i) {
-                        let comment = expr_str.get(byte_index..);
-                        result.comment = Some(comment.unwrap_or_default().to_owned());
-                        break;
+                    // The segment of a separator from a macro also ends at or after it
+                    result.source = sources
+                        .iter()
+                        .find(|(end, _)| origins[pos] <= *end)
+                        .map(|(_, source)| source.clone())
+                        .unwrap_or_default();
+                    if !result.exprs.is_empty() || result.comment.is_some() {
+                        results.push(std::mem::take(&mut result));
                     }
+
+                    weak_credit_acc = None;
+                    weak_debit_acc = None;
+                    self.state = State::default();
+                    self.begin_acc_or_hint(None);
                 }
-                _ => match self.state.estate.clone() {
-                    ExprState::Swap => (),
-                    ExprState::ExprPartAccAndHint(mut node) => match node.find_mut(ch) {
+                '\'' | '‘' | '’' => {
+                    self.end_token(&mut expr, &mut trans)?;
+
+                    self.state.estate = ExprState::ExprComment;
+                    result.comment = Some(String::new());
+                }
+                '"' | '“' | '”' => {
+                    self.end_token(&mut expr, &mut trans)?;
+
+                    memo = Some(String::new());
+                }
+                _ => match self.state.estate {
+                    ExprState::Swap | ExprState::ExprComment => (),
+                    ExprState::ExprPartAccAndHint(node) => match node.find(ch) {
                         Some(nn) => {
                             self.state.staging_token.push(ch);
-                            self.state.estate = ExprState::ExprPartAccAndHint(nn.unsafe_ref());
+                            self.state.estate = ExprState::ExprPartAccAndHint(nn);
                         }
                         None => {
                             if !self.state.staging_token.is_empty() {
-                                self.pop_acc_or_hint_token(node.clone(), &mut expr)?;
+                                self.pop_acc_or_hint_token(node, &mut expr)?;
                             }
 
                             if ch.is_digit(10) || ch == '+' {
@@ -361,7 +955,7 @@ impl Parser {
                                 self.state.pbitset = 0;
                                 self.change_pstate(PriceState::Debit)?;
 
-                                self.state.staging_token.push(ch);
+                                self.push_price_char(ch);
                             } else {
                                 if !self.begin_acc_or_hint(Some(ch)) {
                                     parse_fail!(
@@ -374,13 +968,29 @@ impl Parser {
                     },
                     ExprState::ExprPartPrice => match ch {
                         '0'..='9' | '.' => {
-                            self.state.staging_token.push(ch);
+                            self.push_price_char(ch);
+                        }
+                        // `1100/-100`, shares sold
+                        '-' if matches!(self.state.pstate, PriceState::Shares)
+                            && self.state.staging_token.is_empty() =>
+                        {
+                            self.push_price_char(ch);
                         }
                         _ => {
                             if !self.state.staging_token.is_empty() {
                                 self.pop_price_token(&mut trans)?;
                             }
 
+                            if self.state.step_hint_allowed {
+                                if let Some(len) = self.match_step_hint(&chars[pos..]) {
+                                    let hint = chars[pos..pos + len].iter().collect();
+                                    self.push_step_hint(hint, &mut trans)?;
+                                    skip = len - 1;
+                                    continue;
+                                }
+                            }
+                            self.state.step_hint_allowed = false;
+
                             match ch {
                                 '-' => {
                                     self.change_pstate(PriceState::Reward)?;
@@ -397,6 +1007,12 @@ impl Parser {
                                 '+' => {
                                     self.change_pstate(PriceState::Cashback)?;
                                 }
+                                '!' | '！' => {
+                                    self.change_pstate(PriceState::Fee)?;
+                                }
+                                '分' => {
+                                    self.change_pstate(PriceState::Installment)?;
+                                }
                                 _ => {
                                     if expr.accounts.len() >= 2 && (ch == ',' || ch == '，') {
                                         weak_credit_acc =
@@ -436,15 +1052,7 @@ impl Parser {
             }
         }
 
-        if !expr.is_empty() {
-            result.exprs.push(expr);
-        }
-
-        Ok(result)
-    }
-
-    pub fn reset(&mut self) {
-        self.state = Default::default();
+        Ok(results)
     }
 }
 
@@ -453,18 +1061,28 @@ pub struct ParseError {
     pub msg: String,
     pub ch: char,
     pub pos: usize,
+    /// Char length of the offending input starting at `pos`
+    pub len: usize,
 }
 
 impl std::error::Error for ParseError {}
 
 impl ParseError {
     pub fn new(msg: String, ch: char, pos: usize) -> Self {
-        Self { msg, ch, pos }
+        Self {
+            msg,
+            ch,
+            pos,
+            len: 1,
+        }
     }
 }
 
 pub type ParseResult<T> = std::result::Result<T, ParseError>;
 
+/// End position and source of each segment between separators
+type Sources = Vec<(usize, String)>;
+
 macro_rules! parse_error {
     ($self:ident, $msg:literal $(,)?) => {
         ParseError::new(String::from($msg), $self.state.ch, $self.state.pos)
@@ -488,18 +1106,20 @@ macro_rules! parse_fail {
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let span = if self.len > 1 {
+            format!("{}..{}", self.pos, self.pos + self.len)
+        } else {
+            self.pos.to_string()
+        };
+
         if self.ch == '\0' {
-            writeln!(f, "ParseError at NUL ({}): {}", self.pos, self.msg)
+            writeln!(f, "ParseError at NUL ({}): {}", span, self.msg)
         } else {
-            writeln!(
-                f,
-                "ParseError at '{}' ({}): {}",
-                self.ch, self.pos, self.msg
-            )
+            writeln!(f, "ParseError at '{}' ({}): {}", self.ch, span, self.msg)
         }
     }
 }
 
-use hyoubkp_base::AccountToken;
+use hyoubkp_base::{AccountToken, HintToken};
 pub(crate) use parse_error;
 pub(crate) use parse_fail;
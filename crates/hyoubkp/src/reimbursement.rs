@@ -0,0 +1,76 @@
+use hyoubkp_base::date::Date;
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{Reimbursement, Transaction};
+
+/// A claim not settled yet, or the part of it left
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenItem {
+    pub id: Option<String>,
+    pub date: Date,
+    pub account: String,
+    pub amount: Price,
+    pub orig_expr: Option<String>,
+}
+
+impl std::fmt::Display for OpenItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.date, self.account, self.amount)?;
+        if let Some(expr) = &self.orig_expr {
+            write!(f, " [{}]", expr)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Replays the claims and settlements of `journal` in order. A settlement
+/// closes the oldest claim of the same amount, or else the oldest claims of
+/// its account until the amount is used up. Returns the open items and the
+/// settled amounts exceeding the claims
+pub fn open_items(journal: &[Transaction]) -> (Vec<OpenItem>, Vec<(String, Price)>) {
+    let mut items: Vec<OpenItem> = Vec::new();
+    let mut excess = Vec::new();
+
+    for transaction in journal.iter() {
+        for r in transaction.reimbursements.iter() {
+            match r {
+                Reimbursement::Claim { account, amount } => items.push(OpenItem {
+                    id: transaction.id.clone(),
+                    date: transaction.date,
+                    account: account.clone(),
+                    amount: *amount,
+                    orig_expr: transaction.orig_expr.clone(),
+                }),
+                Reimbursement::Settlement { account, amount } => {
+                    if let Some(i) = items
+                        .iter()
+                        .position(|item| item.account == *account && item.amount == *amount)
+                    {
+                        items.remove(i);
+                        continue;
+                    }
+
+                    let mut left = *amount;
+                    items.retain_mut(|item| {
+                        if item.account != *account || left == Price::default() {
+                            return true;
+                        }
+                        if item.amount > left {
+                            item.amount = item.amount - left;
+                            left = Price::default();
+                            return true;
+                        }
+                        left = left - item.amount;
+                        false
+                    });
+
+                    if left != Price::default() {
+                        excess.push((account.clone(), left));
+                    }
+                }
+            }
+        }
+    }
+
+    (items, excess)
+}
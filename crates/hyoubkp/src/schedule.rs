@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use hyoubkp_base::bail;
+use hyoubkp_base::date::Date;
+use hyoubkp_base::error::{Error, Result};
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{InstallmentPlan, Transaction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recurrence {
+    Day,
+    /// Day of the week, 1 for Monday through 7 for Sunday
+    Week(u8),
+    /// Day of the month, falling on the last day of shorter months
+    Month(u8),
+    /// Month and day of the year, 02-29 falls on 02-28 of common years
+    Year(u8, u8),
+}
+
+impl Recurrence {
+    pub fn is_due(&self, date: Date) -> bool {
+        match *self {
+            Recurrence::Day => true,
+            Recurrence::Week(weekday) => date.weekday() == weekday,
+            Recurrence::Month(day) => date.day() == day.min(date.last_day_of_month()),
+            Recurrence::Year(month, day) => {
+                date.month() == month && date.day() == day.min(date.last_day_of_month())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recurrence::Day => write!(f, "day"),
+            Recurrence::Week(weekday) => write!(f, "week {}", weekday),
+            Recurrence::Month(day) => write!(f, "month {}", day),
+            Recurrence::Year(month, day) => write!(f, "year {:02}-{:02}", month, day),
+        }
+    }
+}
+
+/// A transaction expression recurring as `day|week WEEKDAY|month DAY|year MM-DD EXPR`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub recurrence: Recurrence,
+    pub expr: String,
+}
+
+impl Schedule {
+    /// Dates in `from..=to` the schedule is due on
+    pub fn due_dates(&self, from: Date, to: Date) -> Vec<Date> {
+        let mut dates = Vec::new();
+        let mut date = from;
+
+        while date <= to {
+            if self.recurrence.is_due(date) {
+                dates.push(date);
+            }
+            date = date.succ();
+        }
+
+        dates
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.trim().splitn(2, char::is_whitespace);
+        let unit = words.next().unwrap_or_default();
+        let rest = words.next().unwrap_or_default().trim_start();
+
+        let (recurrence, expr) = match unit {
+            "day" => (Recurrence::Day, rest),
+            "week" | "month" | "year" => {
+                let (arg, expr) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let recurrence = match unit {
+                    "week" => match arg.parse() {
+                        Ok(weekday @ 1..=7) => Recurrence::Week(weekday),
+                        _ => bail!("Invalid weekday '{}', expected 1 (Monday) to 7", arg),
+                    },
+                    "month" => match arg.parse() {
+                        Ok(day @ 1..=31) => Recurrence::Month(day),
+                        _ => bail!("Invalid day of month '{}', expected 1 to 31", arg),
+                    },
+                    _ => match arg.split_once('-').map(|(m, d)| (m.parse(), d.parse())) {
+                        Some((Ok(month @ 1..=12), Ok(day @ 1..=31))) => Recurrence::Year(month, day),
+                        _ => bail!("Invalid day of year '{}', expected MM-DD", arg),
+                    },
+                };
+                (recurrence, expr)
+            }
+            _ => bail!("Unknown recurrence '{}', expected day, week, month or year", unit),
+        };
+
+        if expr.trim().is_empty() {
+            bail!("Expression of the schedule is missing");
+        }
+
+        Ok(Schedule {
+            recurrence,
+            expr: expr.trim().to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.recurrence, self.expr)
+    }
+}
+
+/// An installment plan of a purchase, kept until its last move is generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenInstallment {
+    /// Names the plan in [`ScheduleState`], from the id of the purchase
+    pub key: String,
+    /// Date of the purchase, and of the first move
+    pub date: Date,
+    pub num_base: u32,
+    pub description: Option<String>,
+    pub plan: InstallmentPlan,
+}
+
+impl OpenInstallment {
+    pub fn move_date(&self, period: u32) -> Date {
+        self.date.add_months(period as i32)
+    }
+
+    /// The moves due in `from..=to` with their dates, the status is set by the caller
+    pub fn due_moves(&self, from: Date, to: Date) -> Vec<(Date, Transaction)> {
+        let mut due = Vec::new();
+
+        for period in 0..self.plan.periods {
+            let date = self.move_date(period);
+            if date < from || date > to {
+                continue;
+            }
+
+            let mut moved = self.plan.build_move(period);
+            moved.date = date;
+            moved.num_base = self.num_base;
+            if let Some(desc) = self.description.as_deref() {
+                if !desc.is_empty() {
+                    moved.description = moved.description.map(|d| format!("{} {}", desc, d));
+                }
+            }
+            due.push((date, moved));
+        }
+
+        due
+    }
+}
+
+/// `YYYY-MM-DD plan` followed by the key, number base, periods, amount, account,
+/// statement account, fee account, fee, memo and description, separated by tabs
+impl std::fmt::Display for OpenInstallment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plan = &self.plan;
+        let (fee_account, fee) = match &plan.fee {
+            Some((account, fee)) => (account.as_str(), fee.to_string()),
+            None => ("", String::new()),
+        };
+
+        write!(
+            f,
+            "{} plan\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.date,
+            self.key,
+            self.num_base,
+            plan.periods,
+            plan.amount,
+            plan.account,
+            plan.statement_account,
+            fee_account,
+            fee,
+            plan.memo.as_deref().unwrap_or_default(),
+            self.description.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl FromStr for OpenInstallment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let [
+            head,
+            key,
+            num_base,
+            periods,
+            amount,
+            account,
+            statement_account,
+            fee_account,
+            fee,
+            memo,
+            description,
+        ] = fields[..]
+        else {
+            bail!("Expected 11 fields of an installment plan, but got '{}'", s);
+        };
+        let Some(date) = head.strip_suffix(" plan") else {
+            bail!("Expected 'YYYY-MM-DD plan', but got '{}'", head);
+        };
+        let optional = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+
+        Ok(OpenInstallment {
+            key: key.to_owned(),
+            date: date.parse()?,
+            num_base: num_base.parse()?,
+            description: optional(description),
+            plan: InstallmentPlan {
+                periods: periods.parse()?,
+                amount: amount.parse()?,
+                account: account.to_owned(),
+                statement_account: statement_account.to_owned(),
+                fee: match fee_account {
+                    "" => None,
+                    account => Some((account.to_owned(), fee.parse::<Price>()?)),
+                },
+                memo: optional(memo),
+            },
+        })
+    }
+}
+
+/// The dates each schedule or installment plan has been generated on, keyed by
+/// its text, so that generating an overlapping or earlier range again adds nothing.
+/// Also the installment plans still open and the interest periods accrued, so that
+/// plans of previous sessions go on and interest is not accrued again
+#[derive(Debug, Default, Clone)]
+pub struct ScheduleState {
+    generated: BTreeMap<String, BTreeSet<Date>>,
+    /// Periods `from..to` per account token
+    accrued: BTreeMap<String, BTreeSet<(Date, Date)>>,
+    /// Open installment plans by key
+    installments: BTreeMap<String, OpenInstallment>,
+}
+
+impl ScheduleState {
+    /// Reads `YYYY-MM-DD SCHEDULE`, `YYYY-MM-DD..YYYY-MM-DD interest TOKEN` and
+    /// [`OpenInstallment`] lines
+    pub fn load(input: impl std::io::BufRead) -> Result<Self> {
+        let mut state = Self::default();
+
+        for raw in input.lines() {
+            let raw = raw?;
+            let line = raw.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((date, schedule)) = line.split_once(char::is_whitespace) else {
+                bail!("Expected 'YYYY-MM-DD SCHEDULE', but got '{}'", line);
+            };
+            if schedule.starts_with("plan\t") {
+                // Without trimming the empty fields at the end
+                state.add_installment(raw.trim_start().parse()?);
+                continue;
+            }
+            let period = date.split_once("..");
+            let token = schedule.trim().strip_prefix("interest ");
+            match (period, token) {
+                (Some((from, to)), Some(token)) => {
+                    state.mark_accrued(token.trim(), from.parse()?, to.parse()?)
+                }
+                (Some(_), None) => bail!("Expected 'interest TOKEN', but got '{}'", schedule),
+                (None, _) => state.mark_generated(schedule.trim(), date.parse()?),
+            }
+        }
+
+        Ok(state)
+    }
+
+    pub fn save(&self, mut output: impl std::io::Write) -> Result<()> {
+        for (schedule, dates) in self.generated.iter() {
+            for date in dates.iter() {
+                writeln!(output, "{} {}", date, schedule)?;
+            }
+        }
+        for (token, periods) in self.accrued.iter() {
+            for (from, to) in periods.iter() {
+                writeln!(output, "{}..{} interest {}", from, to, token)?;
+            }
+        }
+        for installment in self.installments() {
+            writeln!(output, "{}", installment)?;
+        }
+
+        Ok(())
+    }
+
+    /// `key` is a [`Schedule`], or the key of an installment plan from
+    /// [`crate::executor::Executor::due_installments`]
+    pub fn is_generated(&self, key: impl std::fmt::Display, date: Date) -> bool {
+        self.generated
+            .get(&key.to_string())
+            .is_some_and(|dates| dates.contains(&date))
+    }
+
+    pub fn mark_generated(&mut self, key: impl std::fmt::Display, date: Date) {
+        self.generated.entry(key.to_string()).or_default().insert(date);
+    }
+
+    /// Interest periods accrued as `(token, from, to)`, see
+    /// [`crate::executor::Executor::mark_interest_accrued`]
+    pub fn accrued(&self) -> impl Iterator<Item = (&str, Date, Date)> {
+        self.accrued.iter().flat_map(|(token, periods)| {
+            periods.iter().map(move |(from, to)| (token.as_str(), *from, *to))
+        })
+    }
+
+    pub fn mark_accrued(&mut self, token: &str, from: Date, to: Date) {
+        self.accrued.entry(token.to_owned()).or_default().insert((from, to));
+    }
+
+    /// Installment plans with moves not generated yet, see
+    /// [`crate::executor::Executor::load_installments`]
+    pub fn installments(&self) -> impl Iterator<Item = &OpenInstallment> {
+        self.installments.values().filter(|installment| {
+            let last = installment.move_date(installment.plan.periods - 1);
+            !self.is_generated(&installment.key, last)
+        })
+    }
+
+    pub fn add_installment(&mut self, installment: OpenInstallment) {
+        self.installments.insert(installment.key.clone(), installment);
+    }
+}
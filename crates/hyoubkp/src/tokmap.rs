@@ -51,14 +51,14 @@ impl TokenMapperKind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TokenMapperDispatch {
     #[cfg(feature = "tokmap_example")]
     Example(tokmap_impl_example::TokenMapperImpl),
     #[cfg(feature = "tokmap_user")]
     User(tokmap_impl_user::TokenMapperImpl),
     #[cfg(feature = "tokmap_rule")]
-    Rule(tokmap_impl_rule::TokenMapperImpl),
+    Rule(Box<tokmap_impl_rule::TokenMapperImpl>),
 }
 
 impl TokenMapperDispatch {
@@ -71,12 +71,28 @@ impl TokenMapperDispatch {
             #[cfg(feature = "tokmap_user")]
             TokenMapperKind::User => Self::User(tokmap_impl_user::TokenMapperImpl::new(options)?),
             #[cfg(feature = "tokmap_rule")]
-            TokenMapperKind::Rule => Self::Rule(tokmap_impl_rule::TokenMapperImpl::new(options)?),
+            TokenMapperKind::Rule => {
+                Self::Rule(Box::new(tokmap_impl_rule::TokenMapperImpl::new(options)?))
+            }
         })
     }
 }
 
+/// Use `mut` before the name to dispatch on `&mut TokenMapperDispatch`
 macro_rules! tokmap_dispatch {
+    (mut $n:ident, $v:expr, $($e:tt)*) => {
+        match $v {
+            #[cfg(feature = "tokmap_example")]
+            TokenMapperDispatch::Example($n) => $($e)*,
+            #[cfg(feature = "tokmap_user")]
+            TokenMapperDispatch::User($n) => $($e)*,
+            #[cfg(feature = "tokmap_rule")]
+            TokenMapperDispatch::Rule($n) => {
+                let $n = &mut **$n;
+                $($e)*
+            }
+        }
+    };
     ($n:ident, $v:expr, $($e:tt)*) => {
         match $v {
             #[cfg(feature = "tokmap_example")]
@@ -84,7 +100,10 @@ macro_rules! tokmap_dispatch {
             #[cfg(feature = "tokmap_user")]
             TokenMapperDispatch::User($n) => $($e)*,
             #[cfg(feature = "tokmap_rule")]
-            TokenMapperDispatch::Rule($n) => $($e)*,
+            TokenMapperDispatch::Rule($n) => {
+                let $n = &**$n;
+                $($e)*
+            }
         }
     };
 }
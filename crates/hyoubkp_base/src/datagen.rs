@@ -27,4 +27,19 @@ pub trait DataGen {
         transactions: &[Transaction],
         number: u32,
     ) -> Result<(), std::io::Error>;
+
+    /// Copies previously generated `input` to `f`, replacing the rows of the
+    /// transaction `id`, or removing them if there is no replacement
+    fn rewrite(
+        &self,
+        _input: impl std::io::Read,
+        _f: impl std::io::Write,
+        _id: &str,
+        _replacement: Option<&Transaction>,
+    ) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Rewriting is not supported by this data-gen backend",
+        ))
+    }
 }
\ No newline at end of file
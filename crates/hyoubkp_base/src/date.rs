@@ -1,6 +1,10 @@
-use chrono::{Datelike, Local};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy)]
+use chrono::{Datelike, Local, NaiveDate};
+
+use crate::error::{bail, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Date(u16, u8, u8);
 
 impl From<&str> for Date {
@@ -17,6 +21,19 @@ impl From<&str> for Date {
     }
 }
 
+impl FromStr for Date {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d") {
+            Ok(d) if (0..=u16::MAX as i32).contains(&d.year()) => {
+                Ok(Date(d.year() as u16, d.month() as u8, d.day() as u8))
+            }
+            _ => bail!("Invalid date '{}', expected YYYY-MM-DD", s.trim()),
+        }
+    }
+}
+
 impl Default for Date {
     fn default() -> Self {
         Date(1970, 1, 1)
@@ -34,4 +51,52 @@ impl Date {
         let now = Local::now();
         Date(now.year() as u16, now.month() as u8, now.day() as u8)
     }
+
+    pub fn year(&self) -> u16 {
+        self.0
+    }
+
+    pub fn month(&self) -> u8 {
+        self.1
+    }
+
+    pub fn day(&self) -> u8 {
+        self.2
+    }
+
+    /// Day of the week, 1 for Monday through 7 for Sunday
+    pub fn weekday(&self) -> u8 {
+        self.to_naive().weekday().number_from_monday() as u8
+    }
+
+    pub fn last_day_of_month(&self) -> u8 {
+        let (year, month) = match self.1 {
+            12 => (self.0 as i32 + 1, 1),
+            m => (self.0 as i32, m as u32 + 1),
+        };
+        NaiveDate::from_ymd_opt(year, month, 1)
+            .and_then(|d| d.pred_opt())
+            .map_or(31, |d| d.day() as u8)
+    }
+
+    /// The same day `n` months later, or the last day of a shorter month
+    pub fn add_months(&self, n: i32) -> Self {
+        let months = self.0 as i32 * 12 + self.1 as i32 - 1 + n;
+        Date((months / 12) as u16, (months % 12) as u8 + 1, 1).with_day(self.2)
+    }
+
+    /// The `day` of the same month, or its last day if the month is shorter
+    pub fn with_day(&self, day: u8) -> Self {
+        Date(self.0, self.1, day.min(self.last_day_of_month()))
+    }
+
+    /// The following day
+    pub fn succ(&self) -> Self {
+        let d = self.to_naive().succ_opt().unwrap_or_default();
+        Date(d.year() as u16, d.month() as u8, d.day() as u8)
+    }
+
+    fn to_naive(self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.0 as i32, self.1 as u32, self.2 as u32).unwrap_or_default()
+    }
 }
\ No newline at end of file
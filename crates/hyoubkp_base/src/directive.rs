@@ -0,0 +1,30 @@
+/// A directive is an input line like `.date 2024-01-01`, which changes the
+/// state used by the following expressions instead of producing a transaction
+#[derive(Debug, Clone)]
+pub struct DirectiveInfo {
+    /// Name without the leading dot
+    pub name: String,
+    /// Arguments, as shown in help, e.g. `YYYY-MM-DD`
+    pub usage: String,
+    pub help: String,
+}
+
+impl DirectiveInfo {
+    pub fn new(name: impl Into<String>, usage: impl Into<String>, help: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            usage: usage.into(),
+            help: help.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for DirectiveInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ".{}", self.name)?;
+        if !self.usage.is_empty() {
+            write!(f, " {}", self.usage)?;
+        }
+        Ok(())
+    }
+}
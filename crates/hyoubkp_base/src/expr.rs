@@ -1,9 +1,19 @@
-use crate::{price::Price, AccountToken, HintToken};
+use crate::{price::Price, transaction::TransactionStatus, AccountToken, HintToken};
 
 #[derive(Default, Debug)]
 pub struct CompoundExpr {
     pub exprs: Vec<Expr>,
     pub comment: Option<String>,
+    /// The part of the input line this transaction is parsed from
+    pub source: String,
+    /// `payee:...` after the prices
+    pub payee: Option<String>,
+    /// `#tag` after the prices
+    pub tags: Vec<String>,
+    /// `key:value` after the prices
+    pub metadata: Vec<(String, String)>,
+    /// `!`, `*` or `**` in front of the transaction
+    pub status: Option<TransactionStatus>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -11,6 +21,8 @@ pub struct Expr {
     pub accounts: Vec<AccountToken>,
     pub hints: Vec<HintToken>,
     pub trans: Vec<ExprTrans>,
+    /// `"..."` after the sub-expression, becomes the memo of its entries
+    pub memo: Option<String>,
 }
 
 impl Expr {
@@ -23,9 +35,29 @@ impl Expr {
 pub struct ExprTrans {
     pub shares: Option<Price>,
     pub price_debit: Price,
-    pub price_credit_chain: Vec<ExprCreditPrice>,
-    pub cash_backs: Vec<Price>,
+    pub price_credit_chain: Vec<ExprStep<ExprCreditPrice>>,
+    pub cash_backs: Vec<ExprStep<Price>>,
+    /// `1000!2`, paid by the credit account on top of the price
+    pub fee: Option<Price>,
     pub multiple: u32,
+    /// `1200分12`, paid in monthly periods, a fee is then charged per period
+    pub installments: Option<u32>,
+}
+
+/// A price step with the hint tokens placed right after it, e.g. `5券` in `20-5券-3积分`
+#[derive(Debug, Clone)]
+pub struct ExprStep<T> {
+    pub value: T,
+    pub hints: Vec<HintToken>,
+}
+
+impl<T> From<T> for ExprStep<T> {
+    fn from(value: T) -> Self {
+        Self {
+            value,
+            hints: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -41,7 +73,9 @@ impl Default for ExprTrans {
             price_debit: Price::default(),
             price_credit_chain: Vec::new(),
             cash_backs: Vec::new(),
+            fee: None,
             multiple: 1,
+            installments: None,
         }
     }
 }
@@ -52,7 +86,9 @@ impl ExprTrans {
             && self.price_debit == Price::default()
             && self.price_credit_chain.is_empty()
             && self.cash_backs.is_empty()
+            && self.fee.is_none()
             && self.multiple == 1
+            && self.installments.is_none()
     }
 
     pub fn is_valid(&self) -> bool {
@@ -66,7 +102,7 @@ impl ExprTrans {
 
         let mut credit_price = self.price_debit.clone();
         for i in self.price_credit_chain.iter() {
-            match i {
+            match &i.value {
                 ExprCreditPrice::Reward(r) => {
                     if r.as_raw() > credit_price.as_raw() {
                         return false;
@@ -1,5 +1,6 @@
 pub mod datagen;
 pub mod date;
+pub mod directive;
 pub mod error;
 pub mod expr;
 pub mod price;
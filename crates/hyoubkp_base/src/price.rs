@@ -4,13 +4,26 @@ use std::{fmt::Display, str::FromStr};
 
 use crate::error::{bail, Error};
 
-#[derive(Default, Clone, Copy, Debug, PartialEq)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Price(i32);
 
 impl FromStr for Price {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('-') {
+            Some(s) => Ok(-Self::parse_unsigned(s)?),
+            None => Self::parse_unsigned(s),
+        }
+    }
+}
+
+impl Price {
+    fn parse_unsigned(s: &str) -> Result<Self, Error> {
+        if s.starts_with('-') {
+            bail!("More than one sign in price '-{}'", s);
+        }
+
         let mut parts = s.split('.');
         let integer_part = parts.next();
         let fractional_part = parts.next();
@@ -46,6 +59,36 @@ impl FromStr for Price {
     }
 }
 
+impl std::ops::Add for Price {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::AddAssign for Price {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0
+    }
+}
+
+impl std::ops::Neg for Price {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+
+impl std::ops::Mul<i32> for Price {
+    type Output = Self;
+
+    fn mul(self, rhs: i32) -> Self::Output {
+        Self(self.0 * rhs)
+    }
+}
+
 impl std::ops::Sub for Price {
     type Output = Self;
 
@@ -56,7 +99,10 @@ impl std::ops::Sub for Price {
 
 impl Display for Price {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{:02}", self.integer_part(), self.fractional_part())
+        if self.0 < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{}.{:02}", self.integer_part().abs(), self.fractional_part())
     }
 }
 
@@ -76,4 +122,25 @@ impl Price {
     pub fn new_unchecked(integer_part: i32, fractional_part: i32) -> Self {
         Self(integer_part * 100 + fractional_part)
     }
+
+    /// Splits into `n` parts of whole cents, the first one takes the remainder
+    pub fn split(&self, n: u32) -> Vec<Self> {
+        let n = n.max(1) as i32;
+        let part = self.0 / n;
+
+        std::iter::once(Self(self.0 - part * (n - 1)))
+            .chain(std::iter::repeat_n(Self(part), n as usize - 1))
+            .collect()
+    }
+
+    /// The share of `part` out of `whole`, rounded to whole cents
+    pub fn prorate(&self, part: Price, whole: Price) -> Self {
+        if whole.0 == 0 {
+            return Self(0);
+        }
+
+        let raw = self.0 as i64 * part.0 as i64;
+        let whole = whole.0 as i64;
+        Self(((2 * raw + raw.signum() * whole.abs()) / (2 * whole)) as i32)
+    }
 }
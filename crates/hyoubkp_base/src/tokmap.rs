@@ -1,13 +1,240 @@
-use crate::transaction::TransactionFactory;
+use std::str::FromStr;
+
+use crate::{
+    date::Date,
+    directive::DirectiveInfo,
+    error::{bail, Error, Result},
+    price::Price,
+    transaction::TransactionFactory,
+    AccountToken,
+};
+
+/// Hint redirecting the debit of an expression to a receivable account, or
+/// with a single account, settling the receivable into that account
+pub const REIMBURSEMENT_HINT: &str = "报销";
+
+/// Hint converting points of the first account to cash, `10/1000` for 1000
+/// points worth 10, credited to the second account or else the first one
+pub const REDEMPTION_HINT: &str = "兑换";
 
 pub trait TokenMapper {
     fn get_version(&self) -> &'static str { "" }
     fn is_option_supported(opt: TokenMapperOption) -> bool;
     fn register_account_tokens(&self) -> Vec<&str>;
     fn register_hint_tokens(&self) -> Vec<&str>;
+    fn register_variables(&self) -> Vec<(String, Price)> { vec![] }
+    fn register_macros(&self) -> Vec<(String, String)> { vec![] }
     fn fallback_account(&self) -> String;
     fn on_account(&self, fac: &mut TransactionFactory, account: &str) -> bool;
-    fn on_reward(&self, fac: &mut TransactionFactory);
+    fn on_reward(&self, fac: &mut TransactionFactory, ctx: &RewardContext);
+    /// Account of the fee of a transfer, the fallback account if not set
+    fn on_fee(&self, _fac: &mut TransactionFactory) {}
+    /// Account holding the unbilled periods, `<credit account>:分期` if not set
+    fn on_installment(&self, _fac: &mut TransactionFactory, _ctx: &InstallmentContext) {}
+    /// Receivable account of [`REIMBURSEMENT_HINT`], the fallback account if not set
+    fn on_reimbursement(&self, _fac: &mut TransactionFactory) {}
+    /// Account of gains and losses realised by selling shares, the fallback
+    /// account if not set
+    fn on_capital_gain(&self, _fac: &mut TransactionFactory) {}
+    fn register_statement_cycles(&self) -> Vec<StatementCycle> { vec![] }
+    fn register_earn_rates(&self) -> Vec<EarnRate> { vec![] }
+    fn register_interest_terms(&self) -> Vec<InterestTerms> { vec![] }
+    /// Income or expense account of the interest, the fallback account if not set
+    fn on_interest(&self, _fac: &mut TransactionFactory, _ctx: &InterestContext) {}
+    fn register_directives(&self) -> Vec<DirectiveInfo> { vec![] }
+    fn on_directive(&mut self, _name: &str, _args: &str) -> Result<()> { Ok(()) }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RewardKind {
+    /// `20-5`, a reward deducted from the price
+    Reward,
+    /// `20@15`, the difference between the price and the credited amount
+    Credit,
+    /// `+5`, money returned to the credit account
+    Cashback,
+    /// Points earned by spending on a card with an [`EarnRate`], the account
+    /// is credited with the points
+    Points,
+}
+
+#[derive(Debug, Clone)]
+pub struct RewardContext {
+    pub kind: RewardKind,
+    pub amount: Price,
+    /// Index of the step in the credit price chain, or in the cashback list
+    pub step: usize,
+    pub debit_tok: Option<AccountToken>,
+    pub credit_tok: Option<AccountToken>,
+}
+
+/// The billing cycle of a credit card. On the statement day, the unbilled
+/// balance is moved to the billed account, which is due on the due day
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementCycle {
+    pub token: AccountToken,
+    pub statement_day: u8,
+    /// Day of the month following the statement if not after the statement day
+    pub due_day: u8,
+    pub unbilled_account: String,
+    pub billed_account: String,
+}
+
+impl StatementCycle {
+    /// The last statement closed before `date`
+    pub fn last_statement(&self, date: Date) -> Date {
+        let statement = date.with_day(self.statement_day);
+        if date > statement {
+            statement
+        } else {
+            date.add_months(-1).with_day(self.statement_day)
+        }
+    }
+
+    pub fn due_date(&self, statement: Date) -> Date {
+        if self.due_day > self.statement_day {
+            statement.with_day(self.due_day)
+        } else {
+            statement.add_months(1).with_day(self.due_day)
+        }
+    }
+
+    /// Whether the last statement before `date` is still to be paid, so that
+    /// a repayment on `date` goes to the billed account
+    pub fn is_billed(&self, date: Date) -> bool {
+        date <= self.due_date(self.last_statement(date))
+    }
+}
+
+/// Points a card earns, `points` for every whole `per` of money spent on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EarnRate {
+    pub token: AccountToken,
+    pub per: Price,
+    pub points: Price,
+    /// Account charged for spending on the card, others of the token earn nothing
+    pub card: String,
+    /// Account holding the points of the card
+    pub account: String,
+}
+
+impl EarnRate {
+    pub fn earned(&self, spent: Price) -> Price {
+        if self.per.as_raw() <= 0 {
+            return Price::default();
+        }
+
+        self.points * (spent.as_raw() / self.per.as_raw())
+    }
+}
+
+/// How the days of an interest period count towards a year
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DayCount {
+    #[default]
+    Act365,
+    Act360,
+    /// Every month counts as 30 days
+    Thirty360,
+}
+
+impl DayCount {
+    /// Part of a year from `date` to the next day
+    pub fn year_fraction(&self, date: Date) -> f64 {
+        match self {
+            DayCount::Act365 => 1.0 / 365.0,
+            DayCount::Act360 => 1.0 / 360.0,
+            DayCount::Thirty360 => {
+                let next = date.succ();
+                let day = |d: Date| d.day().min(30) as i32;
+                let months = (next.year() as i32 - date.year() as i32) * 12 + next.month() as i32
+                    - date.month() as i32;
+                (months * 30 + day(next) - day(date)) as f64 / 360.0
+            }
+        }
+    }
+}
+
+impl FromStr for DayCount {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "act/365" => Self::Act365,
+            "act/360" => Self::Act360,
+            "30/360" => Self::Thirty360,
+            _ => bail!("Unknown day count '{}', expected act/365, act/360 or 30/360", s),
+        })
+    }
+}
+
+impl std::fmt::Display for DayCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DayCount::Act365 => write!(f, "act/365"),
+            DayCount::Act360 => write!(f, "act/360"),
+            DayCount::Thirty360 => write!(f, "30/360"),
+        }
+    }
+}
+
+/// When accrued interest starts to earn interest itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compounding {
+    #[default]
+    Simple,
+    Daily,
+    /// At the end of each month
+    Monthly,
+}
+
+impl FromStr for Compounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "simple" | "none" => Self::Simple,
+            "daily" => Self::Daily,
+            "monthly" => Self::Monthly,
+            _ => bail!("Unknown compounding '{}', expected simple, daily or monthly", s),
+        })
+    }
+}
+
+impl std::fmt::Display for Compounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compounding::Simple => write!(f, "simple"),
+            Compounding::Daily => write!(f, "daily"),
+            Compounding::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// Interest of a savings or loan account, accrued on its daily balance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterestTerms {
+    pub token: AccountToken,
+    /// Annual rate in percent
+    pub rate: Price,
+    pub day_count: DayCount,
+    pub compounding: Compounding,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterestContext {
+    pub token: AccountToken,
+    pub amount: Price,
+    /// Whether the interest is owed on a negative balance
+    pub loan: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstallmentContext {
+    pub periods: u32,
+    pub amount: Price,
+    pub debit_tok: Option<AccountToken>,
+    pub credit_tok: Option<AccountToken>,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
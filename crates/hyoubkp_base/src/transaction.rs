@@ -1,34 +1,243 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 
 use crate::{
     date::Date,
-    expr::{Expr, ExprCreditPrice},
+    error::{bail, Error},
+    expr::{Expr, ExprCreditPrice, ExprTrans},
     price::Price,
-    tokmap::TokenMapper,
+    tokmap::{
+        EarnRate, InstallmentContext, InterestContext, RewardContext, RewardKind, TokenMapper,
+        REDEMPTION_HINT, REIMBURSEMENT_HINT,
+    },
     HintToken,
 };
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Transaction {
+    /// Set when the transaction is committed, stable across amendments
+    pub id: Option<String>,
     pub has_build_error: bool,
     pub date: Date,
     pub num_base: u32,
+    pub status: TransactionStatus,
     pub debit_entries: Vec<Entry>,
     pub credit_entries: Vec<Entry>,
     pub description: Option<String>,
     pub orig_expr: Option<String>,
+    pub payee: Option<String>,
+    pub tags: Vec<String>,
+    pub metadata: Vec<(String, String)>,
+    pub diagnostics: Vec<String>,
+    /// Moves of `1200分12` purchases to the statement account, due monthly
+    pub installments: Vec<InstallmentPlan>,
+    /// Amounts claimed or settled with [`REIMBURSEMENT_HINT`]
+    pub reimbursements: Vec<Reimbursement>,
+    /// Shares sold with `1100/-100`, matched against the lots of their account
+    /// by the executor, which empties it
+    pub sales: Vec<Sale>,
+}
+
+/// A credit entry of shares sold, valued at the proceeds until the cost of the
+/// lots sold is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sale {
+    /// Index in the credit entries
+    pub entry: usize,
+    /// Account of the realised gain, or loss
+    pub gain_account: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reimbursement {
+    /// `中行用餐报销 50`, paid personally and debited to the receivable account
+    Claim { account: String, amount: Price },
+    /// `工行报销 50`, credited to the receivable account
+    Settlement { account: String, amount: Price },
+}
+
+/// An amount booked to `account` and moved to `statement_account` in monthly
+/// periods, the first one on the date of the purchase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallmentPlan {
+    pub periods: u32,
+    pub amount: Price,
+    pub account: String,
+    pub statement_account: String,
+    /// Charged to `statement_account` in each period
+    pub fee: Option<(String, Price)>,
+    pub memo: Option<String>,
+}
+
+impl InstallmentPlan {
+    /// The move of the `period`-th period counting from 0, dated by the caller
+    pub fn build_move(&self, period: u32) -> Transaction {
+        let amount = self.amount.split(self.periods)[period as usize];
+        let mut transaction = Transaction {
+            description: Some(format!("分期 {}/{}", period + 1, self.periods)),
+            ..Default::default()
+        };
+
+        transaction.debit_entries.push(Entry {
+            account: self.account.clone(),
+            amount: Amount::Price(amount),
+            memo: self.memo.clone(),
+        });
+        transaction.credit_entries.push(Entry {
+            account: self.statement_account.clone(),
+            amount: Amount::Price(amount),
+            memo: self.memo.clone(),
+        });
+
+        if let Some((account, fee)) = &self.fee {
+            transaction.debit_entries.push(Entry {
+                account: account.clone(),
+                amount: Amount::Price(*fee),
+                memo: self.memo.clone(),
+            });
+            transaction.credit_entries.push(Entry {
+                account: self.statement_account.clone(),
+                amount: Amount::Price(*fee),
+                memo: self.memo.clone(),
+            });
+        }
+
+        transaction
+    }
+}
+
+impl std::fmt::Display for InstallmentPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in {} periods, {} -> {}",
+            self.amount, self.periods, self.account, self.statement_account
+        )?;
+        if let Some((account, fee)) = &self.fee {
+            write!(f, ", fee {} per period to {}", fee, account)?;
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    #[default]
+    Unreconciled,
+    Pending,
+    Cleared,
+    Reconciled,
+}
+
+impl TransactionStatus {
+    /// Flags written in front of an expression, e.g. `! 中行用餐 20`
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "!" | "！" => Some(Self::Pending),
+            "*" => Some(Self::Cleared),
+            "**" => Some(Self::Reconciled),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unreconciled => "unreconciled",
+            Self::Pending => "pending",
+            Self::Cleared => "cleared",
+            Self::Reconciled => "reconciled",
+        }
+    }
+}
+
+impl FromStr for TransactionStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "unreconciled" | "none" => Self::Unreconciled,
+            "pending" => Self::Pending,
+            "cleared" => Self::Cleared,
+            "reconciled" => Self::Reconciled,
+            _ => match Self::from_flag(s) {
+                Some(status) => status,
+                None => bail!("Unknown transaction status '{}'", s),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub account: String,
     pub amount: Amount,
+    pub memo: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Amount {
     Shares(Price, Price),
     Price(Price),
+    /// Shares sold out of the lot bought on the date, valued at their cost
+    Lot(Price, Price, Date),
+    /// Points, and their cash value when redeemed
+    Points(Price, Option<Price>),
+}
+
+/// The unit an amount is counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Commodity {
+    Currency,
+    Shares,
+    Points,
+}
+
+impl Amount {
+    pub fn commodity(&self) -> Commodity {
+        match self {
+            Amount::Shares(_, _) | Amount::Lot(_, _, _) => Commodity::Shares,
+            Amount::Price(_) => Commodity::Currency,
+            Amount::Points(_, _) => Commodity::Points,
+        }
+    }
+
+    pub fn quantity(&self) -> Price {
+        match self {
+            Amount::Shares(s, _) | Amount::Lot(s, _, _) | Amount::Points(s, _) => *s,
+            Amount::Price(p) => *p,
+        }
+    }
+
+    /// The commodity a transaction is balanced in, shares and redeemed points
+    /// are balanced by their value
+    pub fn value_commodity(&self) -> Commodity {
+        match self {
+            Amount::Shares(_, _) | Amount::Price(_) | Amount::Lot(_, _, _) => {
+                Commodity::Currency
+            }
+            Amount::Points(_, Some(_)) => Commodity::Currency,
+            Amount::Points(_, None) => Commodity::Points,
+        }
+    }
+
+    pub fn value(&self) -> Price {
+        match self {
+            Amount::Shares(_, p) | Amount::Lot(_, p, _) => *p,
+            Amount::Price(p) => *p,
+            Amount::Points(n, v) => v.unwrap_or(*n),
+        }
+    }
+}
+
+impl std::fmt::Display for Commodity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Commodity::Currency => write!(f, "CNY"),
+            Commodity::Shares => write!(f, "shares"),
+            Commodity::Points => write!(f, "points"),
+        }
+    }
 }
 
 impl std::fmt::Display for Amount {
@@ -36,6 +245,9 @@ impl std::fmt::Display for Amount {
         match self {
             Amount::Shares(s, p) => write!(f, "{} / {}", s, p),
             Amount::Price(p) => write!(f, "{}", p),
+            Amount::Lot(s, p, date) => write!(f, "{} / {} {{{}}}", s, p, date),
+            Amount::Points(n, None) => write!(f, "{} points", n),
+            Amount::Points(n, Some(v)) => write!(f, "{} points / {}", n, v),
         }
     }
 }
@@ -46,16 +258,40 @@ impl std::fmt::Display for Transaction {
             writeln!(f, "Expression: {}", expr)?;
         }
         writeln!(f, "Date: {}, num base: {}", self.date, self.num_base)?;
+        if self.status != TransactionStatus::Unreconciled {
+            writeln!(f, "Status: {}", self.status.as_str())?;
+        }
         writeln!(
             f,
             "Transaction desc: {}",
             self.description.as_deref().unwrap_or_default()
         )?;
-        for e in self.debit_entries.iter() {
-            writeln!(f, "{} debit {}", e.account, e.amount)?
+        if let Some(payee) = &self.payee {
+            writeln!(f, "Payee: {}", payee)?;
         }
-        for e in self.credit_entries.iter() {
-            writeln!(f, "{} credit {}", e.account, e.amount)?
+        if !self.tags.is_empty() {
+            writeln!(f, "Tags: #{}", self.tags.join(" #"))?;
+        }
+        for (key, value) in self.metadata.iter() {
+            writeln!(f, "Meta: {}: {}", key, value)?;
+        }
+        for d in self.diagnostics.iter() {
+            writeln!(f, "Diagnostic: {}", d)?;
+        }
+        for plan in self.installments.iter() {
+            writeln!(f, "Installment: {}", plan)?;
+        }
+        for (e, dc) in self
+            .debit_entries
+            .iter()
+            .map(|e| (e, "debit"))
+            .chain(self.credit_entries.iter().map(|e| (e, "credit")))
+        {
+            write!(f, "{} {} {}", e.account, dc, e.amount)?;
+            if let Some(memo) = &e.memo {
+                write!(f, " \"{}\"", memo)?;
+            }
+            writeln!(f)?;
         }
 
         Ok(())
@@ -66,6 +302,114 @@ impl Transaction {
     pub fn is_empty(&self) -> bool {
         self.credit_entries.is_empty() && self.debit_entries.is_empty()
     }
+
+    /// Debit total minus credit total of every commodity that does not balance
+    pub fn imbalances(&self) -> Vec<(Commodity, Price)> {
+        let mut totals = BTreeMap::new();
+
+        for e in self.debit_entries.iter() {
+            *totals.entry(e.amount.value_commodity()).or_default() += e.amount.value();
+        }
+        for e in self.credit_entries.iter() {
+            *totals.entry(e.amount.value_commodity()).or_default() += -e.amount.value();
+        }
+
+        totals
+            .into_iter()
+            .filter(|(_, total)| *total != Price::default())
+            .collect()
+    }
+
+    /// Merges entries with the same account and commodity on each side, the
+    /// memo of a merged entry lists the amounts and memos of its parts
+    pub fn merge_entries(&mut self) {
+        fn merge(entries: Vec<Entry>) -> Vec<Entry> {
+            let mut merged: Vec<(Entry, Vec<Entry>)> = Vec::new();
+
+            for entry in entries {
+                // Lots stay apart to keep their cost
+                match merged.iter_mut().find(|(e, _)| {
+                    e.account == entry.account
+                        && e.amount.commodity() == entry.amount.commodity()
+                        && e.amount.value_commodity() == entry.amount.value_commodity()
+                        && !matches!(e.amount, Amount::Lot(..))
+                        && !matches!(entry.amount, Amount::Lot(..))
+                }) {
+                    Some((e, parts)) => {
+                        e.amount = match (e.amount, entry.amount) {
+                            (Amount::Shares(s1, p1), Amount::Shares(s2, p2)) => {
+                                Amount::Shares(s1 + s2, p1 + p2)
+                            }
+                            (Amount::Points(n1, v1), Amount::Points(n2, v2)) => {
+                                Amount::Points(n1 + n2, v1.zip(v2).map(|(v1, v2)| v1 + v2))
+                            }
+                            (a, b) => Amount::Price(a.value() + b.value()),
+                        };
+                        parts.push(entry);
+                    }
+                    None => merged.push((entry.clone(), vec![entry])),
+                }
+            }
+
+            merged
+                .into_iter()
+                .map(|(mut e, parts)| {
+                    if parts.len() > 1 {
+                        e.memo = Some(
+                            parts
+                                .iter()
+                                .map(|p| match &p.memo {
+                                    Some(memo) => format!("{} {}", p.amount, memo),
+                                    None => p.amount.to_string(),
+                                })
+                                .collect::<Vec<_>>()
+                                .join(" + "),
+                        );
+                    }
+                    e
+                })
+                .collect()
+        }
+
+        self.debit_entries = merge(std::mem::take(&mut self.debit_entries));
+        self.credit_entries = merge(std::mem::take(&mut self.credit_entries));
+    }
+
+    /// Reports every imbalance as a diagnostic. If `fallback_account` is given, the
+    /// remainder is also posted to it so the transaction balances explicitly.
+    pub fn check_balance(&mut self, fallback_account: Option<&str>) {
+        for (commodity, imbalance) in self.imbalances() {
+            self.has_build_error = true;
+            self.diagnostics.push(format!(
+                "Debit and credit differ by {} in {}",
+                imbalance, commodity
+            ));
+
+            if let Some(account) = fallback_account {
+                let amount = |p| match commodity {
+                    Commodity::Points => Amount::Points(p, None),
+                    _ => Amount::Price(p),
+                };
+                let entry = Entry {
+                    account: account.to_owned(),
+                    amount: Amount::Price(Price::default()),
+                    memo: None,
+                };
+
+                if imbalance.as_raw() > 0 {
+                    self.credit_entries.push(Entry {
+                        amount: amount(imbalance),
+                        ..entry
+                    });
+                } else {
+                    self.debit_entries.push(Entry {
+                        amount: amount(-imbalance),
+                        ..entry
+                    });
+                }
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -77,6 +421,8 @@ pub struct TransactionFactory {
     credit_account: Option<String>,
     debit_account: Option<String>,
     hints_map: HashMap<HintToken, bool>,
+    step_hints_map: HashMap<HintToken, bool>,
+    post_imbalance: bool,
 
     current_account: Option<String>,
 }
@@ -105,6 +451,20 @@ pub struct TransactionFactory {
 //}
 
 impl TransactionFactory {
+    /// Posts the remainder of an unbalanced transaction to the fallback account
+    pub fn set_post_imbalance(&mut self, enable: bool) {
+        self.post_imbalance = enable;
+    }
+
+    /// Date of the transaction, set before any expression
+    pub fn set_date(&mut self, date: Date) {
+        self.transaction.date = date;
+    }
+
+    pub fn date(&self) -> Date {
+        self.transaction.date
+    }
+
     pub fn set_account(&mut self, account: String) {
         self.current_account = Some(account);
     }
@@ -118,6 +478,10 @@ impl TransactionFactory {
     }
 
     pub fn check_hint(&mut self, hint: &str) -> bool {
+        if self.check_step_hint(hint) {
+            return true;
+        }
+
         let hint = self.hints_map.get_mut(hint);
         match hint {
             Some(hint) => {
@@ -128,6 +492,17 @@ impl TransactionFactory {
         }
     }
 
+    /// Only checks the hints attached to the price step currently being mapped
+    pub fn check_step_hint(&mut self, hint: &str) -> bool {
+        match self.step_hints_map.get_mut(hint) {
+            Some(hint) => {
+                *hint = true;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn remove_hint(&mut self, hint: &str) {
         self.hints_map.remove(hint);
     }
@@ -154,6 +529,17 @@ impl TransactionFactory {
         }
     }
 
+    /// Maps an account token as if it was on the credit side of an expression
+    pub fn resolve_credit_account(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        tok: &str,
+    ) -> Option<String> {
+        self.credit_tok = Some(tok.to_owned());
+        self.map_account(1, token_mapper, tok);
+        self.credit_account.take()
+    }
+
     fn map_account(&mut self, cr_or_dr: u32, token_mapper: &impl TokenMapper, tok: &str) {
         self.current_account = None;
 
@@ -170,6 +556,124 @@ impl TransactionFactory {
         }
     }
 
+    fn map_reward(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        kind: RewardKind,
+        amount: Price,
+        step: usize,
+        hints: &[HintToken],
+    ) -> String {
+        self.current_account = None;
+        self.step_hints_map = hints.iter().map(|h| (h.clone(), false)).collect();
+
+        let ctx = RewardContext {
+            kind,
+            amount,
+            step,
+            debit_tok: self.debit_tok.clone(),
+            credit_tok: self.credit_tok.clone(),
+        };
+        token_mapper.on_reward(self, &ctx);
+
+        if self.step_hints_map.iter().any(|(_, accessed)| !accessed) {
+            self.transaction.has_build_error = true;
+        }
+        self.step_hints_map.clear();
+
+        std::mem::take(&mut self.current_account)
+            .unwrap_or_else(|| token_mapper.fallback_account())
+    }
+
+    fn map_installment(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        periods: u32,
+        amount: Price,
+    ) -> String {
+        self.current_account = None;
+
+        let ctx = InstallmentContext {
+            periods,
+            amount,
+            debit_tok: self.debit_tok.clone(),
+            credit_tok: self.credit_tok.clone(),
+        };
+        token_mapper.on_installment(self, &ctx);
+
+        std::mem::take(&mut self.current_account).unwrap_or_else(|| {
+            self.credit_account
+                .as_ref()
+                .map(|a| a.clone() + ":分期")
+                .unwrap_or_else(|| token_mapper.fallback_account())
+        })
+    }
+
+    fn map_reimbursement(&mut self, token_mapper: &impl TokenMapper) -> String {
+        self.current_account = None;
+        token_mapper.on_reimbursement(self);
+
+        std::mem::take(&mut self.current_account).unwrap_or_else(|| {
+            self.transaction.has_build_error = true;
+            token_mapper.fallback_account()
+        })
+    }
+
+    /// Books the points earned by spending `spent` on the card of `rate`
+    fn accrue_points(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        rate: &EarnRate,
+        spent: Price,
+        memo: &Option<String>,
+    ) {
+        let points = rate.earned(spent);
+        if points <= Price::default() {
+            return;
+        }
+
+        let account = self.map_reward(token_mapper, RewardKind::Points, points, 0, &[]);
+        self.transaction.debit_entries.push(Entry {
+            account: rate.account.clone(),
+            amount: Amount::Points(points, None),
+            memo: memo.clone(),
+        });
+        self.transaction.credit_entries.push(Entry {
+            account,
+            amount: Amount::Points(points, None),
+            memo: memo.clone(),
+        });
+    }
+
+    /// Income or expense account of interest accrued outside an expression
+    pub fn map_interest(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        ctx: &InterestContext,
+    ) -> String {
+        self.current_account = None;
+        token_mapper.on_interest(self, ctx);
+
+        std::mem::take(&mut self.current_account)
+            .unwrap_or_else(|| token_mapper.fallback_account())
+    }
+
+    fn map_capital_gain(&mut self, token_mapper: &impl TokenMapper) -> String {
+        self.current_account = None;
+        token_mapper.on_capital_gain(self);
+
+        std::mem::take(&mut self.current_account)
+            .unwrap_or_else(|| token_mapper.fallback_account())
+    }
+
+    fn map_fee(&mut self, token_mapper: &impl TokenMapper) -> String {
+        self.current_account = None;
+        token_mapper.on_fee(self);
+
+        std::mem::take(&mut self.current_account)
+            .unwrap_or_else(|| token_mapper.fallback_account())
+    }
+
     pub fn set_expr(&mut self, token_mapper: &impl TokenMapper, expr: &Expr) {
         self.hints_map
             .iter_mut()
@@ -199,7 +703,66 @@ impl TransactionFactory {
             self.map_account(2, token_mapper, tok);
         }
 
+        let reimbursement = match self.hints_map.get_mut(REIMBURSEMENT_HINT) {
+            Some(accessed) if expr.hints.iter().any(|h| h == REIMBURSEMENT_HINT) => {
+                *accessed = true;
+                let account = self.map_reimbursement(token_mapper);
+
+                if debit_tok.is_some() {
+                    self.debit_account = Some(account.clone());
+                } else if credit_tok.is_some() {
+                    // Settlement, the single account receives the money
+                    self.debit_account = self.credit_account.take();
+                    self.credit_account = Some(account.clone());
+                }
+                Some(account)
+            }
+            _ => None,
+        };
+
+        let earn_rate = credit_tok.and_then(|tok| {
+            token_mapper
+                .register_earn_rates()
+                .into_iter()
+                .find(|rate| rate.token == tok)
+        });
+        let redemption = match self.hints_map.get_mut(REDEMPTION_HINT) {
+            Some(accessed) if expr.hints.iter().any(|h| h == REDEMPTION_HINT) => {
+                *accessed = true;
+
+                match &earn_rate {
+                    Some(rate) => {
+                        if debit_tok.is_none() {
+                            self.debit_account = self.credit_account.take();
+                        }
+                        self.credit_account = Some(rate.account.clone());
+                    }
+                    None => {
+                        self.transaction.has_build_error = true;
+                        self.transaction.diagnostics.push(format!(
+                            "No points of '{}' to redeem",
+                            credit_tok.unwrap_or_default()
+                        ));
+                    }
+                }
+                true
+            }
+            _ => false,
+        };
+        let entries_len = (
+            self.transaction.debit_entries.len(),
+            self.transaction.credit_entries.len(),
+        );
+
         for trans in expr.trans.iter() {
+            if !trans.is_valid() {
+                self.transaction.has_build_error = true;
+                self.transaction.diagnostics.push(format!(
+                    "Price {} is not positive or can not cover its rewards",
+                    trans.price_debit
+                ));
+            }
+
             if trans.cash_backs.is_empty() {
                 if self.debit_account.is_none() || self.credit_account.is_none() {
                     self.transaction.has_build_error = true;
@@ -219,9 +782,12 @@ impl TransactionFactory {
                             .map(|a| a.clone())
                             .unwrap_or_else(|| token_mapper.fallback_account()),
                         amount: match trans.shares {
-                            Some(s) => Amount::Shares(s, trans.price_debit),
-                            None => Amount::Price(trans.price_debit),
+                            Some(s) if s.as_raw() > 0 && !redemption => {
+                                Amount::Shares(s, trans.price_debit)
+                            }
+                            _ => Amount::Price(trans.price_debit),
                         },
+                        memo: expr.memo.clone(),
                     });
 
                     if trans.price_credit_chain.is_empty() {
@@ -232,32 +798,34 @@ impl TransactionFactory {
                                 .map(|a| a.clone())
                                 .unwrap_or_else(|| token_mapper.fallback_account()),
                             amount: Amount::Price(trans.price_debit),
+                            memo: expr.memo.clone(),
                         });
                     } else {
                         let mut price = trans.price_debit.clone();
 
-                        for pc in trans.price_credit_chain.iter() {
+                        for (step, pc) in trans.price_credit_chain.iter().enumerate() {
                             let reward;
+                            let kind;
 
-                            match pc {
+                            match &pc.value {
                                 ExprCreditPrice::Reward(r) => {
                                     reward = r.clone();
                                     price = price - reward;
+                                    kind = RewardKind::Reward;
                                 }
                                 ExprCreditPrice::Credit(c) => {
                                     reward = price - *c;
                                     price = c.clone();
+                                    kind = RewardKind::Credit;
                                 }
                             }
 
-                            self.current_account = None;
-                            token_mapper.on_reward(self);
+                            let account =
+                                self.map_reward(token_mapper, kind, reward, step, &pc.hints);
                             self.transaction.credit_entries.push(Entry {
-                                account: self
-                                    .current_account
-                                    .to_owned()
-                                    .unwrap_or_else(|| token_mapper.fallback_account()),
+                                account,
                                 amount: Amount::Price(reward),
+                                memo: expr.memo.clone(),
                             });
                         }
 
@@ -268,19 +836,76 @@ impl TransactionFactory {
                                 .map(|a| a.clone())
                                 .unwrap_or_else(|| token_mapper.fallback_account()),
                             amount: Amount::Price(price),
+                            memo: expr.memo.clone(),
+                        });
+                    }
+
+                    if redemption {
+                        let entry = self.transaction.credit_entries.last_mut().unwrap();
+                        match trans.shares {
+                            Some(points) if points.as_raw() > 0 => {
+                                entry.amount = Amount::Points(points, Some(entry.amount.value()));
+                            }
+                            _ => {
+                                self.transaction.has_build_error = true;
+                                self.transaction.diagnostics.push(format!(
+                                    "Points redeemed for {} are missing, expected `{}/POINTS`",
+                                    trans.price_debit, trans.price_debit
+                                ));
+                            }
+                        }
+                    } else if let Some(shares) = trans.shares.filter(|s| s.as_raw() < 0) {
+                        let gain_account = self.map_capital_gain(token_mapper);
+                        let entry = self.transaction.credit_entries.len() - 1;
+                        let sold = &mut self.transaction.credit_entries[entry];
+                        sold.amount = Amount::Shares(-shares, sold.amount.value());
+                        self.transaction.sales.push(Sale {
+                            entry,
+                            gain_account,
+                        });
+                    }
+
+                    let charged = self.transaction.credit_entries.last().unwrap();
+                    let spent = charged.amount.value();
+                    // A transfer from the bank account of the same token earns nothing
+                    let earning = earn_rate.clone().filter(|rate| rate.card == charged.account);
+
+                    if let Some(periods) = trans.installments {
+                        self.book_installment(token_mapper, trans, periods, &expr.memo);
+                    } else if let Some(fee) = trans.fee {
+                        let account = self.map_fee(token_mapper);
+                        self.transaction.debit_entries.push(Entry {
+                            account,
+                            amount: Amount::Price(fee),
+                            memo: expr.memo.clone(),
+                        });
+                        self.transaction.credit_entries.push(Entry {
+                            account: self
+                                .credit_account
+                                .clone()
+                                .unwrap_or_else(|| token_mapper.fallback_account()),
+                            amount: Amount::Price(fee),
+                            memo: expr.memo.clone(),
                         });
                     }
+
+                    if let (Some(rate), false, Some(_)) = (&earning, redemption, debit_tok) {
+                        self.accrue_points(token_mapper, rate, spent, &expr.memo);
+                    }
                 }
 
-                for e in trans.cash_backs.iter() {
-                    self.current_account = None;
-                    token_mapper.on_reward(self);
+                for (step, e) in trans.cash_backs.iter().enumerate() {
+                    let account = self.map_reward(
+                        token_mapper,
+                        RewardKind::Cashback,
+                        e.value,
+                        step,
+                        &e.hints,
+                    );
                     self.transaction.credit_entries.push(Entry {
-                        account: self
-                            .current_account
-                            .to_owned()
-                            .unwrap_or_else(|| token_mapper.fallback_account()),
-                        amount: Amount::Price(e.clone()),
+                        account,
+                        amount: Amount::Price(e.value.clone()),
+                        memo: expr.memo.clone(),
                     });
 
                     self.transaction.debit_entries.push(Entry {
@@ -289,11 +914,64 @@ impl TransactionFactory {
                             .as_ref()
                             .map(|a| a.clone())
                             .unwrap_or_else(|| token_mapper.fallback_account()),
-                        amount: Amount::Price(e.clone()),
+                        amount: Amount::Price(e.value.clone()),
+                        memo: expr.memo.clone(),
                     });
                 }
             }
         }
+
+        if let Some(account) = reimbursement {
+            let sum = |entries: &[Entry]| {
+                entries
+                    .iter()
+                    .filter(|e| e.account == account)
+                    .fold(Price::default(), |sum, e| sum + e.amount.value())
+            };
+            let claimed = sum(&self.transaction.debit_entries[entries_len.0..]);
+            let settled = sum(&self.transaction.credit_entries[entries_len.1..]);
+
+            if claimed != Price::default() {
+                self.transaction.reimbursements.push(Reimbursement::Claim {
+                    account: account.clone(),
+                    amount: claimed,
+                });
+            }
+            if settled != Price::default() {
+                self.transaction.reimbursements.push(Reimbursement::Settlement {
+                    account,
+                    amount: settled,
+                });
+            }
+        }
+    }
+
+    /// Moves the amount just credited to the installment account, and plans
+    /// the monthly moves back to the credit account
+    fn book_installment(
+        &mut self,
+        token_mapper: &impl TokenMapper,
+        trans: &ExprTrans,
+        periods: u32,
+        memo: &Option<String>,
+    ) {
+        let Some(entry) = self.transaction.credit_entries.last() else {
+            return;
+        };
+        let (statement_account, amount) = (entry.account.clone(), entry.amount.value());
+
+        let account = self.map_installment(token_mapper, periods, amount);
+        let fee = trans.fee.map(|fee| (self.map_fee(token_mapper), fee));
+
+        self.transaction.credit_entries.last_mut().unwrap().account = account.clone();
+        self.transaction.installments.push(InstallmentPlan {
+            periods,
+            amount,
+            account,
+            statement_account,
+            fee,
+            memo: memo.clone(),
+        });
     }
 
     pub fn build(mut self, token_mapper: &impl TokenMapper) -> Transaction {
@@ -305,9 +983,14 @@ impl TransactionFactory {
             self.transaction.credit_entries.push(Entry {
                 account: token_mapper.fallback_account(),
                 amount: Amount::Price(Price::default()),
+                memo: None,
             });
         }
 
+        let fallback_account = token_mapper.fallback_account();
+        self.transaction
+            .check_balance(self.post_imbalance.then_some(fallback_account.as_str()));
+
         self.transaction
     }
 }
@@ -1,12 +1,18 @@
 use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context as _, Result};
 use clap::ValueEnum;
 use hyoubkp::{
     datagen::{DataGenDispatch, DataGenKind},
+    directive::{split_directive, Directive},
+    executor::Executor,
+    schedule::ScheduleState,
     tokmap::TokenMapperKind,
 };
-use hyoubkp_base::{datagen::DataGen, tokmap::TokenMapperOption};
+use hyoubkp_base::{
+    datagen::DataGen, date::Date, tokmap::TokenMapperOption, transaction::Transaction,
+};
 
 #[derive(clap::Parser, Debug)]
 #[clap(author, version, about)]
@@ -23,10 +29,53 @@ struct Command {
     #[clap(long)]
     print_token_mapper_options: bool,
 
+    /// print all available directives of the token mapper
+    #[clap(long)]
+    list_directives: bool,
+
     /// Input file that contains the expressions, NL-splited. default stdin
     #[clap(short = 'i', long)]
     input: Option<String>,
 
+    /// File of opening balances, `ACCOUNT AMOUNT` or `ACCOUNT COST/SHARES` per line
+    #[clap(long)]
+    opening_balances: Option<String>,
+
+    /// File of committed expressions for `!!` and `!TOKEN`, read at start and
+    /// written back at the end
+    #[clap(long)]
+    history: Option<String>,
+
+    /// Generate the transactions of `.every` schedules, the installment moves
+    /// and the closing of card statements due from this date, after the input
+    /// is processed
+    #[clap(long)]
+    generate_from: Option<Date>,
+
+    /// Last date to generate scheduled transactions for, default today
+    #[clap(long, requires = "generate_from")]
+    generate_to: Option<Date>,
+
+    /// File remembering the scheduled transactions already generated, the
+    /// installment plans still open and the interest already accrued, so that
+    /// they are not generated again and the plans go on
+    #[clap(long)]
+    schedule_state: Option<String>,
+
+    /// Accrue interest from this date on the accounts with interest terms,
+    /// after the input is processed and the scheduled transactions generated
+    #[clap(long)]
+    interest_from: Option<Date>,
+
+    /// End of the interest period, default today
+    #[clap(long, requires = "interest_from")]
+    interest_to: Option<Date>,
+
+    /// Print the outstanding reimbursements to stderr after the input is
+    /// processed
+    #[clap(long)]
+    report_reimbursements: bool,
+
     /// Output file, default stdout
     #[clap(short = 'o', long)]
     output: Option<String>,
@@ -35,7 +84,8 @@ struct Command {
     #[clap(short = 'd', long, default_value = "str")]
     datagen: DataGenKind,
 
-    /// Data-gen backend options (Not implemented)
+    /// Data-gen backend options as key=value,
+    /// e.g. currency=USD, shares=FUND or points=PTS for beancount
     #[clap(short = 'D', long)]
     datagen_options: Vec<String>,
 
@@ -50,6 +100,15 @@ struct Command {
     /// (Not implemented)
     #[clap(short = 'A', long, default_value_t = false)]
     treat_ambiguity_as_error: bool,
+
+    /// Post the remainder of unbalanced transactions to the fallback account,
+    /// instead of leaving it to the importing program
+    #[clap(long, default_value_t = false)]
+    post_imbalance: bool,
+
+    /// Merge entries with the same account on the same side into one split
+    #[clap(long, default_value_t = false)]
+    merge_entries: bool,
 }
 
 fn main() -> Result<()> {
@@ -69,37 +128,279 @@ fn main() -> Result<()> {
 
     let tokmap_options = args.token_mapper_options.into_iter().collect();
 
-    let mut executor = hyoubkp::executor::Executor::new(args.token_mapper, &tokmap_options)?;
-    let datagen_impl = DataGenDispatch::new(args.datagen);
+    let mut executor = Executor::new(args.token_mapper, &tokmap_options)?;
+    executor.enable_ledger();
+    if args.post_imbalance {
+        executor.enable_imbalance_posting();
+    }
+    if args.merge_entries {
+        executor.enable_entry_merging();
+    }
+    if let Some(filename) = args.history.as_ref().filter(|f| Path::new(f).exists()) {
+        executor.load_history(std::fs::read_to_string(filename)?.lines().map(String::from));
+    }
+    if let Some(filename) = &args.opening_balances {
+        executor.load_opening_balances(BufReader::new(std::fs::File::open(filename)?))?;
+    }
 
-    let input: Box<dyn BufRead> = match args.input {
-        Some(filename) => Box::new(BufReader::new(std::fs::File::open(filename)?)),
-        None => Box::new(BufReader::new(std::io::stdin())),
-    };
+    executor.register_directive(Directive::new(
+        "include",
+        "FILE",
+        "Process FILE, resolved relative to the including file. \
+        Directive state is inherited and restored after the file ends",
+        |_, _| hyoubkp_base::bail!("'.include' is only supported in input files"),
+    ));
 
-    let mut output: Box<dyn Write> = match args.output {
+    if args.list_directives {
+        for info in executor.list_directives() {
+            println!("{}\n    {}", info, info.help);
+        }
+
+        return Ok(());
+    }
+
+    let output: Box<dyn Write> = match args.output {
         Some(filename) => Box::new(std::fs::File::create(filename)?),
         None => Box::new(std::io::stdout()),
     };
 
-    let mut number = 0;
-    for expr in input.lines() {
-        let expr = expr?;
-
-        if expr.is_empty() {
-            continue;
-        } else if expr.starts_with('.') {
-            executor.parse_directive(expr)?;
-        } else {
-            let trans = executor.parse_expr(expr)?;
-            datagen_impl.write_to(&mut output, std::slice::from_ref(&trans), number)?;
-            number += 1;
+    let mut datagen_impl = DataGenDispatch::new(args.datagen);
+    for option in args.datagen_options.iter() {
+        datagen_impl.set_option(option)?;
+    }
+
+    let mut ctx = Context {
+        executor,
+        datagen_impl,
+        output,
+        number: 0,
+        include_stack: vec![],
+    };
+
+    // Before the input, whose `.interest` directives must see the periods accrued
+    let state_file = args.schedule_state.as_deref().map(Path::new);
+    let mut state = match state_file.filter(|f| f.exists()) {
+        Some(f) => ScheduleState::load(BufReader::new(std::fs::File::open(f)?))?,
+        None => ScheduleState::default(),
+    };
+    for (token, from, to) in state.accrued() {
+        ctx.executor.mark_interest_accrued(token, from, to);
+    }
+    ctx.executor.load_installments(state.installments().cloned());
+
+    let result = match args.input {
+        Some(filename) => ctx.process_file(Path::new(&filename)),
+        None => ctx.process_input(BufReader::new(std::io::stdin()), "<stdin>", None),
+    };
+    // The output already holds what was accrued or generated before a failure
+    if let Some(f) = state_file {
+        ctx.save_state(&mut state, f)?;
+    }
+    result?;
+
+    if let Some(from) = args.generate_from {
+        let to = args.generate_to.unwrap_or_else(Date::today);
+        let result = ctx.generate_scheduled(from, to, &mut state);
+
+        if let Some(f) = state_file {
+            ctx.save_state(&mut state, f)?;
+        }
+        result?;
+    }
+
+    if let Some(from) = args.interest_from {
+        let to = args.interest_to.unwrap_or_else(Date::today);
+        let transactions = ctx.executor.accrue_interest(from, to)?;
+        ctx.write_transactions(transactions)?;
+
+        if let Some(f) = state_file {
+            ctx.save_state(&mut state, f)?;
+        }
+    }
+
+    if args.report_reimbursements {
+        let items = ctx.executor.open_reimbursements();
+        for item in items.iter() {
+            eprintln!("{}", item);
         }
+        let total = items
+            .iter()
+            .fold(hyoubkp_base::price::Price::default(), |sum, i| sum + i.amount);
+        eprintln!("Outstanding: {} in {} item(s)", total, items.len());
+    }
+
+    if let Some(filename) = &args.history {
+        std::fs::write(filename, ctx.executor.history().join("\n"))?;
     }
 
     Ok(())
 }
 
+struct Context {
+    executor: Executor,
+    datagen_impl: DataGenDispatch,
+    output: Box<dyn Write>,
+    number: u32,
+    /// Canonical paths of the files being processed, outermost first
+    include_stack: Vec<PathBuf>,
+}
+
+impl Context {
+    fn process_file(&mut self, path: &Path) -> Result<()> {
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("Cannot open '{}'", path.display()))?;
+
+        if let Some(pos) = self.include_stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.include_stack[pos..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            bail!("Include cycle: {}", cycle.join(" -> "));
+        }
+
+        let input = BufReader::new(std::fs::File::open(&canonical)?);
+
+        self.include_stack.push(canonical.clone());
+        let result = self.process_input(input, &path.display().to_string(), Some(&canonical));
+        self.include_stack.pop();
+
+        result
+    }
+
+    fn process_input(
+        &mut self,
+        input: impl BufRead,
+        name: &str,
+        path: Option<&Path>,
+    ) -> Result<()> {
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+
+            self.process_line(&line, path)
+                .with_context(|| format!("at {}:{}", name, i + 1))?;
+        }
+
+        Ok(())
+    }
+
+    fn process_line(&mut self, line: &str, path: Option<&Path>) -> Result<()> {
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if line.starts_with('.') {
+            if let Some(("include", file)) = split_directive(line) {
+                let base = match path.and_then(Path::parent) {
+                    Some(dir) => dir.to_path_buf(),
+                    None => std::env::current_dir()?,
+                };
+
+                let state = self.executor.directive_state();
+                let result = self.process_file(&base.join(file));
+                self.executor.restore_directive_state(state);
+
+                return result;
+            }
+
+            // Directives like `.interest` may commit transactions
+            let committed = self.executor.journal().len();
+            self.executor.parse_directive(line)?;
+            let transactions = self.executor.journal()[committed..].to_vec();
+            return self.write_transactions(transactions);
+        }
+
+        let transactions = self.executor.commit(line)?;
+        self.write_transactions(transactions)
+    }
+
+    fn generate_scheduled(
+        &mut self,
+        from: Date,
+        to: Date,
+        state: &mut ScheduleState,
+    ) -> Result<()> {
+        let due: Vec<_> = self
+            .executor
+            .due_schedules(from, to)
+            .into_iter()
+            .filter(|(date, schedule)| !state.is_generated(schedule, *date))
+            .map(|(date, schedule)| (date, schedule.clone()))
+            .collect();
+
+        for (date, schedule) in due {
+            let transactions = self
+                .executor
+                .commit_scheduled(&schedule, date)
+                .with_context(|| format!("at schedule '{}' of {}", schedule, date))?;
+            self.write_transactions(transactions)?;
+            state.mark_generated(&schedule, date);
+        }
+
+        // After the schedules, which may purchase in installments themselves
+        let due: Vec<_> = self
+            .executor
+            .due_installments(from, to)
+            .into_iter()
+            .filter(|(date, key, _)| !state.is_generated(key, *date))
+            .collect();
+
+        for (date, key, transaction) in due {
+            let transaction = self.executor.commit_transaction(transaction);
+            self.write_transactions(vec![transaction])?;
+            state.mark_generated(&key, date);
+        }
+
+        // Last, as the unbilled balances include everything generated above
+        let due: Vec<_> = self
+            .executor
+            .due_statements(from, to)
+            .into_iter()
+            .map(|(date, cycle)| (date, format!("statement {}", cycle.token), cycle.clone()))
+            .filter(|(date, key, _)| !state.is_generated(key, *date))
+            .collect();
+
+        for (date, key, cycle) in due {
+            if let Some(transaction) = self.executor.build_statement(&cycle, date)? {
+                let transaction = self.executor.commit_transaction(transaction);
+                self.write_transactions(vec![transaction])?;
+            }
+            state.mark_generated(&key, date);
+        }
+
+        Ok(())
+    }
+
+    /// Records the interest accrued and the installment plans opened so far, and
+    /// writes the state to `file`
+    fn save_state(&self, state: &mut ScheduleState, file: &Path) -> Result<()> {
+        for (token, from, to) in self.executor.accrued_interest() {
+            state.mark_accrued(token, *from, *to);
+        }
+        for installment in self.executor.open_installments() {
+            state.add_installment(installment);
+        }
+        state.save(std::fs::File::create(file)?)?;
+
+        Ok(())
+    }
+
+    fn write_transactions(&mut self, transactions: Vec<Transaction>) -> Result<()> {
+        for trans in transactions {
+            for d in trans.diagnostics.iter() {
+                eprintln!("{}: {}", trans.orig_expr.as_deref().unwrap_or_default(), d);
+            }
+
+            self.datagen_impl
+                .write_to(&mut self.output, std::slice::from_ref(&trans), self.number)?;
+            self.number += 1;
+        }
+
+        Ok(())
+    }
+}
+
 fn parse_key_value<T: ValueEnum>(s: &str) -> Result<(T, String), String> {
     let parts: Vec<&str> = s.splitn(2, '=').collect();
     if parts.len() != 2 {
@@ -1,5 +1,199 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 
-pub enum Format {
-    Plain,
-    GnuCashCSV,
-}
\ No newline at end of file
+use hyoubkp_base::{
+    bail,
+    datagen::DataGen,
+    error::Result,
+    price::Price,
+    transaction::{Amount, Transaction, TransactionStatus},
+};
+
+/// Root accounts of the token mappers, named by beancount options
+const ROOT_ACCOUNT_OPTIONS: [(&str, &str); 5] = [
+    ("name_assets", "资产"),
+    ("name_liabilities", "负债"),
+    ("name_equity", "权益"),
+    ("name_income", "收入"),
+    ("name_expenses", "支出"),
+];
+
+#[derive(Debug)]
+pub struct DataGenImpl {
+    currency: String,
+    shares_commodity: String,
+    points_commodity: String,
+    /// Accounts with an `open` directive written
+    opened: RefCell<HashSet<String>>,
+}
+
+impl Default for DataGenImpl {
+    fn default() -> Self {
+        Self {
+            currency: String::from("CNY"),
+            shares_commodity: String::from("SHARES"),
+            points_commodity: String::from("POINTS"),
+            opened: RefCell::default(),
+        }
+    }
+}
+
+impl DataGenImpl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of a commodity by `currency`, `shares` or `points`
+    pub fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        let valid = value.starts_with(|ch: char| ch.is_ascii_uppercase())
+            && value.chars().all(|ch| {
+                ch.is_ascii_uppercase() || ch.is_ascii_digit() || "'._-".contains(ch)
+            });
+        if !valid {
+            bail!("'{}' is not a beancount commodity", value);
+        }
+
+        match key {
+            "currency" => self.currency = value.to_owned(),
+            "shares" => self.shares_commodity = value.to_owned(),
+            "points" => self.points_commodity = value.to_owned(),
+            _ => bail!(
+                "Unknown beancount option '{}', expected currency, shares or points",
+                key
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+impl DataGen for DataGenImpl {
+    fn write_to(
+        &self,
+        mut f: impl std::io::Write,
+        transactions: &[Transaction],
+        number: u32,
+    ) -> std::result::Result<(), std::io::Error> {
+        if number == 0 {
+            for (option, root) in ROOT_ACCOUNT_OPTIONS {
+                writeln!(f, "option \"{}\" \"{}\"", option, root)?;
+            }
+            writeln!(f, "option \"operating_currency\" \"{}\"", self.currency)?;
+            writeln!(f)?;
+        }
+
+        for trans in transactions.iter() {
+            let mut opened = self.opened.borrow_mut();
+            let mut opening = false;
+            for e in trans.debit_entries.iter().chain(trans.credit_entries.iter()) {
+                let account = Self::account(&e.account);
+                if !opened.contains(&account) {
+                    writeln!(f, "{} open {}", trans.date, account)?;
+                    opened.insert(account);
+                    opening = true;
+                }
+            }
+            if opening {
+                writeln!(f)?;
+            }
+
+            write!(f, "{} {}", trans.date, Self::flag(trans.status))?;
+            if let Some(payee) = &trans.payee {
+                write!(f, " {}", Self::quote(payee))?;
+            }
+            write!(
+                f,
+                " {}",
+                Self::quote(trans.description.as_deref().unwrap_or_default())
+            )?;
+            for tag in trans.tags.iter() {
+                write!(f, " #{}", tag)?;
+            }
+            writeln!(f)?;
+
+            let mut notes = Vec::new();
+            for (key, value) in trans.metadata.iter() {
+                if Self::is_metadata_key(key) {
+                    writeln!(f, "  {}: {}", key, Self::quote(value))?;
+                } else {
+                    notes.push(format!("{}:{}", key, value));
+                }
+            }
+            if !notes.is_empty() {
+                writeln!(f, "  notes: {}", Self::quote(&notes.join(" ")))?;
+            }
+
+            for (e, credit) in trans
+                .debit_entries
+                .iter()
+                .map(|x| (x, false))
+                .chain(trans.credit_entries.iter().map(|x| (x, true)))
+            {
+                write!(
+                    f,
+                    "  {}  {}",
+                    Self::account(&e.account),
+                    self.amount(&e.amount, credit)
+                )?;
+                if let Some(memo) = &e.memo {
+                    write!(f, " ; {}", memo)?;
+                }
+                writeln!(f)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DataGenImpl {
+    /// Credits have their quantity negated, but not their cost or price
+    fn amount(&self, amount: &Amount, credit: bool) -> String {
+        let q = |p: Price| if credit { -p } else { p };
+
+        match *amount {
+            Amount::Shares(s, p) => {
+                format!("{} {} {{{{{} {}}}}}", q(s), self.shares_commodity, p, self.currency)
+            }
+            Amount::Price(p) => format!("{} {}", q(p), self.currency),
+            Amount::Lot(s, p, date) => format!(
+                "{} {} {{{{{} {}, {}}}}}",
+                q(s),
+                self.shares_commodity,
+                p,
+                self.currency,
+                date
+            ),
+            Amount::Points(n, None) => format!("{} {}", q(n), self.points_commodity),
+            Amount::Points(n, Some(v)) => {
+                format!("{} {} @@ {} {}", q(n), self.points_commodity, v, self.currency)
+            }
+        }
+    }
+
+    fn flag(status: TransactionStatus) -> &'static str {
+        match status {
+            TransactionStatus::Pending => "!",
+            _ => "*",
+        }
+    }
+
+    /// Beancount account components can not contain whitespaces
+    fn account(account: &str) -> String {
+        account.split_whitespace().collect::<Vec<_>>().join("-")
+    }
+
+    /// Beancount metadata keys begin with a lowercase letter
+    fn is_metadata_key(key: &str) -> bool {
+        key.starts_with(|ch: char| ch.is_ascii_lowercase())
+            && key
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+    }
+
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
@@ -1,5 +1,9 @@
-use csv::WriterBuilder;
-use hyoubkp_base::{datagen::DataGen, transaction::{Amount, Transaction}};
+use csv::{ReaderBuilder, Writer, WriterBuilder};
+use hyoubkp_base::{
+    datagen::DataGen,
+    price::Price,
+    transaction::{Amount, Entry, Transaction, TransactionStatus},
+};
 use uuid::Uuid;
 
 #[derive(serde::Serialize)]
@@ -12,6 +16,8 @@ struct GnuCashCSVRow {
     full_account_name: String,
     amount_num: String,
     value_num: String,
+    memo: String,
+    notes: String,
 }
 
 #[derive(Debug)]
@@ -43,65 +49,164 @@ impl DataGen for DataGenImpl {
                 "Full Account Name",
                 "Amount Num.",
                 "Value Num.",
+                "Memo",
+                "Notes",
             ])?;
         }
 
         for trans in transactions.iter() {
-            let transaction_id = Uuid::new_v4().as_simple().to_string();
-
-            for (e, dc) in trans
-                .credit_entries
-                .iter()
-                .map(|x| (x, 2))
-                .chain(trans.debit_entries.iter().map(|x| (x, 1)))
-            {
-                let mut description = trans.description.clone().unwrap_or_default();
-                if description == "" {
-                    description = String::from(" ");
-                }
+            Self::write_rows(&mut wtr, trans, number + trans.num_base)?;
+        }
+
+        Ok(())
+    }
+
+    fn rewrite(
+        &self,
+        input: impl std::io::Read,
+        f: impl std::io::Write,
+        id: &str,
+        replacement: Option<&Transaction>,
+    ) -> Result<(), std::io::Error> {
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(input);
+        let mut wtr = WriterBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_writer(f);
+        let mut replaced = false;
+
+        for record in rdr.records() {
+            let record = record?;
 
-                if dc == 1 {
-                    wtr.serialize(GnuCashCSVRow {
-                        date: trans.date.to_string(),
-                        transaction_id: transaction_id.clone(),
-                        number: number + trans.num_base,
-                        description: description,
-                        reconcile: String::from("n"),
-                        full_account_name: e.account.clone(),
-                        amount_num: Self::amount_to_amount(&e.amount),
-                        value_num: Self::amount_to_value(&e.amount),
-                    })?;
-                } else if dc == 2 {
-                    wtr.serialize(GnuCashCSVRow {
-                        date: trans.date.to_string(),
-                        transaction_id: transaction_id.clone(),
-                        number: number + trans.num_base,
-                        description: description,
-                        reconcile: String::from("n"),
-                        full_account_name: e.account.clone(),
-                        amount_num: format!("-{}", Self::amount_to_amount(&e.amount)),
-                        value_num: format!("-{}", Self::amount_to_value(&e.amount)),
-                    })?;
+            if record.get(1) != Some(id) {
+                wtr.write_record(&record)?;
+                continue;
+            }
+
+            if !replaced {
+                replaced = true;
+
+                if let Some(trans) = replacement {
+                    let number = record.get(2).and_then(|n| n.parse().ok()).unwrap_or_default();
+                    Self::write_rows(&mut wtr, trans, number)?;
                 }
             }
         }
 
-        Ok(())
+        if !replaced {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Transaction '{}' is not found", id),
+            ));
+        }
+
+        wtr.flush()
     }
 }
 
 impl DataGenImpl {
-    fn amount_to_amount(amount: &Amount) -> String {
+    fn write_rows(
+        wtr: &mut Writer<impl std::io::Write>,
+        trans: &Transaction,
+        number: u32,
+    ) -> Result<(), std::io::Error> {
+        let transaction_id = match &trans.id {
+            Some(id) => id.clone(),
+            None => Uuid::new_v4().as_simple().to_string(),
+        };
+        let notes = Self::transaction_notes(trans);
+
+        for (e, dc) in trans
+            .credit_entries
+            .iter()
+            .map(|x| (x, 2))
+            .chain(trans.debit_entries.iter().map(|x| (x, 1)))
+        {
+            let mut description = trans.description.clone().unwrap_or_default();
+            if description == "" {
+                description = String::from(" ");
+            }
+
+            if dc == 1 {
+                wtr.serialize(GnuCashCSVRow {
+                    date: trans.date.to_string(),
+                    transaction_id: transaction_id.clone(),
+                    number,
+                    description,
+                    reconcile: Self::status_to_reconcile(trans.status),
+                    full_account_name: e.account.clone(),
+                    amount_num: Self::amount_to_amount(&e.amount).to_string(),
+                    value_num: Self::amount_to_value(&e.amount).to_string(),
+                    memo: Self::entry_memo(e),
+                    notes: notes.clone(),
+                })?;
+            } else if dc == 2 {
+                wtr.serialize(GnuCashCSVRow {
+                    date: trans.date.to_string(),
+                    transaction_id: transaction_id.clone(),
+                    number,
+                    description,
+                    reconcile: Self::status_to_reconcile(trans.status),
+                    full_account_name: e.account.clone(),
+                    amount_num: (-Self::amount_to_amount(&e.amount)).to_string(),
+                    value_num: (-Self::amount_to_value(&e.amount)).to_string(),
+                    memo: Self::entry_memo(e),
+                    notes: notes.clone(),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// GnuCash has no payee, tag or metadata field, so they are all kept in Notes
+    fn transaction_notes(trans: &Transaction) -> String {
+        let mut notes = Vec::new();
+
+        if let Some(payee) = &trans.payee {
+            notes.push(format!("payee:{}", payee));
+        }
+        notes.extend(trans.tags.iter().map(|t| format!("#{}", t)));
+        notes.extend(trans.metadata.iter().map(|(k, v)| format!("{}:{}", k, v)));
+
+        notes.join(" ")
+    }
+
+    /// The value of a lot sold is its cost, and the lot is kept in Memo
+    fn entry_memo(e: &Entry) -> String {
+        let memo = e.memo.clone().unwrap_or_default();
+
+        match e.amount {
+            Amount::Lot(_, _, date) if memo.is_empty() => format!("lot {}", date),
+            Amount::Lot(_, _, date) => format!("{} lot {}", memo, date),
+            _ => memo,
+        }
+    }
+
+    fn status_to_reconcile(status: TransactionStatus) -> String {
+        match status {
+            TransactionStatus::Unreconciled | TransactionStatus::Pending => String::from("n"),
+            TransactionStatus::Cleared => String::from("c"),
+            TransactionStatus::Reconciled => String::from("y"),
+        }
+    }
+
+    fn amount_to_amount(amount: &Amount) -> Price {
         match amount {
-            Amount::Shares(s, _) => s.to_string(),
-            Amount::Price(p) => p.to_string(),
+            Amount::Shares(s, _) | Amount::Lot(s, _, _) | Amount::Points(s, _) => *s,
+            Amount::Price(p) => *p,
         }
     }
 
-    fn amount_to_value(amount: &Amount) -> String {
+    fn amount_to_value(amount: &Amount) -> Price {
         match amount {
-            Amount::Shares(_, p) => p.to_string(),
-            Amount::Price(p) => p.to_string(),
+            Amount::Shares(_, p) | Amount::Lot(_, p, _) => *p,
+            Amount::Price(p) => *p,
+            // Points earned are worth nothing until redeemed
+            Amount::Points(_, v) => v.unwrap_or_default(),
         }
     }
 }
\ No newline at end of file
@@ -9,9 +9,11 @@ use std::{
 };
 
 use hyoubkp::datagen::{DataGenDispatch, DataGenKind};
+use hyoubkp::directive::split_directive;
 use hyoubkp::executor::Executor;
 use hyoubkp::tokmap::TokenMapperKind;
 use hyoubkp_base::datagen::DataGen;
+use hyoubkp_base::transaction::Transaction;
 #[allow(unused_imports)]
 use hyoubkp_base::tokmap::TokenMapperOption;
 
@@ -23,6 +25,7 @@ struct AppMainView {
     number: i32,
     output_file_name: PathBuf,
     output_file_name_backup: PathBuf,
+    history_file_name: PathBuf,
     #[allow(dead_code)]
     rule_file_name: PathBuf,
 }
@@ -32,6 +35,9 @@ static mut APPCTX: OnceCell<AppMainView> = OnceCell::new();
 static USERDEFAULTS_KEY_NUMBER: &CStr = c"hm_number";
 static USERDEFAULTS_KEY_STAGING_EXPR: &CStr = c"hm_staging_expr";
 
+/// Number of committed expressions kept for `!!` and `!TOKEN` across launches
+static HISTORY_LIMIT: usize = 1000;
+
 #[no_mangle]
 extern "C" fn app_action_MainViewController_self_Load(
     vc: *mut ::std::os::raw::c_void,
@@ -48,6 +54,7 @@ extern "C" fn app_action_MainViewController_self_Load(
 
     let output_file_name = document_path.join("output.csv");
     let rule_file_name = document_path.join("rule.toml");
+    let history_file_name = document_path.join("history.txt");
 
     if !std::fs::exists(&rule_file_name).unwrap() {
         std::fs::OpenOptions::new()
@@ -96,6 +103,12 @@ extern "C" fn app_action_MainViewController_self_Load(
     match Executor::new(tokmap_kind, &tokmap_options) {
         Ok(mut executor) => {
             executor.enable_realtime_date();
+            executor.load_history(
+                std::fs::read_to_string(&history_file_name)
+                    .unwrap_or_default()
+                    .lines()
+                    .map(String::from),
+            );
 
             tokmap_version = executor.get_tokmap_version();
 
@@ -106,6 +119,7 @@ extern "C" fn app_action_MainViewController_self_Load(
                         number,
                         output_file_name,
                         output_file_name_backup: document_path.join("output.csv.bak"),
+                        history_file_name,
                         rule_file_name,
                     })
                     .unwrap()
@@ -177,8 +191,54 @@ extern "C" fn app_action_MainViewController_button1_Tapped(
 
     let ctx = unsafe { APPCTX.get_mut().unwrap() };
 
-    match ctx.executor.parse_expr(expr) {
-        Ok(trans) => {
+    // The output is rewritten before the journal, which is kept if it fails
+    if let Some((name @ ("undo" | "amend"), args)) = split_directive(expr) {
+        let result = match (name, ctx.executor.journal().last().cloned()) {
+            ("undo", Some(orig)) => rewrite_output(ctx, &orig, None).map(|()| {
+                ctx.executor.undo_last();
+            }),
+            ("undo", None) => Err(String::from("Nothing to undo")),
+            (_, Some(orig)) => {
+                let n = ctx.executor.journal().len() - 1;
+                ctx.executor
+                    .preview_amend(n, args)
+                    .map_err(|e| e.message)
+                    .and_then(|replacement| rewrite_output(ctx, &orig, Some(&replacement)))
+                    .and_then(|()| ctx.executor.amend(n, args).map(|_| ()).map_err(|e| e.message))
+            }
+            (_, None) => Err(String::from("Nothing to amend")),
+        };
+
+        match result {
+            Ok(()) => {
+                save_history(ctx);
+                unsafe {
+                    appui_uikit_textField_set_text(text_field1, c"".as_ptr());
+                    appui_userdefaults_set_string(
+                        USERDEFAULTS_KEY_STAGING_EXPR.as_ptr(),
+                        c"".as_ptr(),
+                    );
+                }
+            }
+            Err(e) => {
+                let e = CString::new(e).unwrap_or_default();
+                unsafe {
+                    appui_uikit_alertctrl(
+                        vc,
+                        c"Error".as_ptr(),
+                        e.as_ptr(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    )
+                };
+            }
+        }
+
+        return;
+    }
+
+    match ctx.executor.commit(expr) {
+        Ok(transactions) => {
             let output_file_name = &ctx.output_file_name;
             let output_file_name_backup = &ctx.output_file_name_backup;
 
@@ -195,14 +255,17 @@ extern "C" fn app_action_MainViewController_button1_Tapped(
                 .open(output_file_name)
                 .unwrap();
 
-            datagen_impl
-                .write_to(&mut file, std::slice::from_ref(&trans), ctx.number as u32)
-                .unwrap();
+            for trans in transactions.iter() {
+                datagen_impl
+                    .write_to(&mut file, std::slice::from_ref(trans), ctx.number as u32)
+                    .unwrap();
 
-            ctx.number += 1;
+                ctx.number += 1;
+            }
             unsafe {
                 appui_userdefaults_set_i32(USERDEFAULTS_KEY_NUMBER.as_ptr(), ctx.number);
             }
+            save_history(ctx);
 
             unsafe {
                 appui_uikit_textField_set_text(text_field1, c"".as_ptr());
@@ -224,6 +287,32 @@ extern "C" fn app_action_MainViewController_button1_Tapped(
     }
 }
 
+fn save_history(ctx: &AppMainView) {
+    let history = ctx.executor.history();
+    let history = &history[history.len().saturating_sub(HISTORY_LIMIT)..];
+    std::fs::write(&ctx.history_file_name, history.join("\n")).unwrap();
+}
+
+/// Rewrites the rows of a transaction committed in this session, `.undo` and
+/// `.amend EXPR` act on the last one
+fn rewrite_output(
+    ctx: &AppMainView,
+    orig: &Transaction,
+    replacement: Option<&Transaction>,
+) -> Result<(), String> {
+    let id = orig.id.as_deref().unwrap_or_default();
+    let input = std::fs::read(&ctx.output_file_name).map_err(|e| e.to_string())?;
+
+    let mut output = Vec::new();
+    DataGenDispatch::new(DataGenKind::GnuCash)
+        .rewrite(input.as_slice(), &mut output, id, replacement)
+        .map_err(|e| e.to_string())?;
+
+    std::fs::copy(&ctx.output_file_name, &ctx.output_file_name_backup)
+        .and_then(|_| std::fs::write(&ctx.output_file_name, output))
+        .map_err(|e| e.to_string())
+}
+
 #[no_mangle]
 extern "C" fn app_action_MainViewController_textField1_DidChange(
     vc: *mut ::std::os::raw::c_void,
@@ -237,12 +326,23 @@ extern "C" fn app_action_MainViewController_textField1_DidChange(
 
     let expr = text.to_str().unwrap();
 
-    let executor = unsafe { &mut APPCTX.get_mut().unwrap().executor };
+    let executor = unsafe { &APPCTX.get().unwrap().executor };
 
     let label1 = unsafe { appui_MainViewController_label1(vc) };
 
-    let text = CString::new(match executor.parse_expr(expr) {
-        Ok(trans) => trans.to_string(),
+    let expr = match split_directive(expr) {
+        Some(("amend", args)) => args,
+        _ => expr,
+    };
+
+    let text = CString::new(match executor.preview(expr) {
+        Ok(transactions) if transactions.len() == 1 => transactions[0].to_string(),
+        Ok(transactions) => transactions
+            .iter()
+            .enumerate()
+            .map(|(i, trans)| format!("[{}/{}]\n{}", i + 1, transactions.len(), trans))
+            .collect::<Vec<_>>()
+            .join("\n"),
         Err(e) => e.to_string(),
     })
     .unwrap_or_default();
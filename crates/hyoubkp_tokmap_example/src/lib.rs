@@ -3,14 +3,21 @@ pub mod user;
 
 use std::collections::HashMap;
 
-use hyoubkp_base::tokmap::{TokenMapper, TokenMapperOption};
+use hyoubkp_base::tokmap::{
+    Compounding, DayCount, EarnRate, InterestContext, InterestTerms, RewardContext, RewardKind,
+    StatementCycle, TokenMapper, TokenMapperOption,
+};
+use hyoubkp_base::price::Price;
 use hyoubkp_base::transaction::TransactionFactory;
 use hyoubkp_base::error::Result;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TokenMapperImpl {
     bank_account_tokens: Vec<&'static str>,
     expense_account_tokens: Vec<&'static str>,
+    investment_account_tokens: Vec<&'static str>,
+    loan_account_tokens: Vec<&'static str>,
+    statement_cycles: Vec<StatementCycle>,
 }
 
 impl TokenMapperImpl {
@@ -18,6 +25,24 @@ impl TokenMapperImpl {
         Ok(Self {
             bank_account_tokens: vec!["工行", "农行", "中行", "建行", "交行", "邮储"],
             expense_account_tokens: vec!["用餐", "杂项"],
+            investment_account_tokens: vec!["基金"],
+            loan_account_tokens: vec!["房贷"],
+            statement_cycles: vec![
+                StatementCycle {
+                    token: "中行".into(),
+                    statement_day: 5,
+                    due_day: 25,
+                    unbilled_account: "负债:信用卡:中行 1234".into(),
+                    billed_account: "负债:信用卡:中行 1234:已出账单".into(),
+                },
+                StatementCycle {
+                    token: "建行".into(),
+                    statement_day: 20,
+                    due_day: 10,
+                    unbilled_account: "负债:信用卡:建行 8888".into(),
+                    billed_account: "负债:信用卡:建行 8888:已出账单".into(),
+                },
+            ],
         })
     }
 }
@@ -30,11 +55,44 @@ impl TokenMapper for TokenMapperImpl {
     fn register_account_tokens(&self) -> Vec<&'static str> {
         let mut v = self.bank_account_tokens.clone();
         v.extend(self.expense_account_tokens.iter());
+        v.extend(self.investment_account_tokens.iter());
+        v.extend(self.loan_account_tokens.iter());
         v
     }
 
     fn register_hint_tokens(&self) -> Vec<&'static str> {
-        vec!["还款", "未出账单", "利息", "信用卡", "储蓄卡"]
+        vec!["还款", "未出账单", "利息", "信用卡", "储蓄卡", "券", "积分"]
+    }
+
+    fn register_statement_cycles(&self) -> Vec<StatementCycle> {
+        self.statement_cycles.clone()
+    }
+
+    fn register_earn_rates(&self) -> Vec<EarnRate> {
+        vec![EarnRate {
+            token: "建行".into(),
+            per: Price::new_unchecked(20, 0),
+            points: Price::new_unchecked(1, 0),
+            card: "负债:信用卡:建行 8888".into(),
+            account: "资产:积分:建行".into(),
+        }]
+    }
+
+    fn register_interest_terms(&self) -> Vec<InterestTerms> {
+        vec![
+            InterestTerms {
+                token: "工行".into(),
+                rate: Price::new_unchecked(0, 35),
+                day_count: DayCount::Act365,
+                compounding: Compounding::Simple,
+            },
+            InterestTerms {
+                token: "房贷".into(),
+                rate: Price::new_unchecked(4, 20),
+                day_count: DayCount::Act360,
+                compounding: Compounding::Monthly,
+            },
+        ]
     }
 
     fn fallback_account(&self) -> String {
@@ -59,6 +117,7 @@ impl TokenMapper for TokenMapperImpl {
             "中行" => {
                 self.bank_common(
                     fac,
+                    account,
                     "资产:银行:BOC 中国银行",
                     "负债:信用卡:中行 1234",
                     "负债:信用卡:中行 1234:已出账单",
@@ -67,6 +126,7 @@ impl TokenMapper for TokenMapperImpl {
             "建行" => {
                 self.bank_common(
                     fac,
+                    account,
                     "资产:银行:CCB 建设银行",
                     "负债:信用卡:建行 8888",
                     "负债:信用卡:建行 8888:已出账单",
@@ -78,13 +138,19 @@ impl TokenMapper for TokenMapperImpl {
             "杂项" => {
                 fac.set_account("支出:杂项".into());
             }
+            "基金" => {
+                fac.set_account("资产:投资:基金".into());
+            }
+            "房贷" => {
+                fac.set_account("负债:贷款:房贷".into());
+            }
             _ => return false,
         };
 
         true
     }
 
-    fn on_reward(&self, fac: &mut TransactionFactory) {
+    fn on_reward(&self, fac: &mut TransactionFactory, ctx: &RewardContext) {
         fac.set_account("收入:优惠券变现".into());
 
         if fac.check_debit(&["用餐", "杂项"]) {
@@ -93,13 +159,45 @@ impl TokenMapper for TokenMapperImpl {
         if fac.check_hint("利息") {
             fac.set_account("收入:利息".into());
         }
+        if fac.check_step_hint("券") {
+            fac.set_account("收入:优惠券变现".into());
+        }
+        if fac.check_step_hint("积分") {
+            fac.set_account("收入:积分变现".into());
+        }
+        if ctx.kind == RewardKind::Points {
+            fac.set_account("收入:积分".into());
+        }
+    }
+
+    fn on_fee(&self, fac: &mut TransactionFactory) {
+        fac.set_account("支出:手续费".into());
+    }
+
+    fn on_reimbursement(&self, fac: &mut TransactionFactory) {
+        fac.set_account("资产:应收款:报销".into());
+    }
+
+    fn on_capital_gain(&self, fac: &mut TransactionFactory) {
+        fac.set_account("收入:投资收益".into());
+    }
+
+    fn on_interest(&self, fac: &mut TransactionFactory, ctx: &InterestContext) {
+        if ctx.loan {
+            fac.set_account("支出:利息".into());
+        } else {
+            fac.set_account("收入:利息".into());
+        }
     }
 }
 
 impl TokenMapperImpl {
+    /// A repayment goes to the bill while the last statement is due, unless
+    /// hinted with `未出账单`
     fn bank_common(
         &self,
         fac: &mut TransactionFactory,
+        token: &str,
         saving_acc_name: &str,
         credit_card_name: &str,
         credit_card_bill_name: &str,
@@ -115,7 +213,14 @@ impl TokenMapperImpl {
             fac.set_account(saving_acc_name.into());
         }
         if fac.is_debit() && fac.check_hint("还款") && !fac.check_hint("未出账单") {
-            fac.set_account(credit_card_bill_name.into());
+            let billed = self
+                .statement_cycles
+                .iter()
+                .find(|c| c.token == token)
+                .is_none_or(|c| c.is_billed(fac.date()));
+            if billed {
+                fac.set_account(credit_card_bill_name.into());
+            }
         }
     }
 }
@@ -6,7 +6,11 @@ use std::path::Path;
 
 use hyoubkp_base::error::Result;
 use hyoubkp_base::{
-    tokmap::{TokenMapper, TokenMapperOption},
+    price::Price,
+    tokmap::{
+        EarnRate, InstallmentContext, InterestContext, InterestTerms, RewardContext,
+        StatementCycle, TokenMapper, TokenMapperOption,
+    },
     transaction::TransactionFactory,
 };
 use rule::*;
@@ -15,7 +19,7 @@ pub type Tag = String;
 pub type AccountTokenOrTag = String;
 pub use hyoubkp_base::{AccountToken, HintToken};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TokenMapperImpl {
     rule: CookedRule,
 }
@@ -51,29 +55,99 @@ impl TokenMapper for TokenMapperImpl {
         self.rule.hints.iter().map(AsRef::as_ref).collect()
     }
 
+    fn register_variables(&self) -> Vec<(String, Price)> {
+        self.rule.variables.clone()
+    }
+
+    fn register_macros(&self) -> Vec<(String, String)> {
+        self.rule.macros.clone()
+    }
+
+    fn register_statement_cycles(&self) -> Vec<StatementCycle> {
+        self.rule.cards.clone()
+    }
+
+    fn register_earn_rates(&self) -> Vec<EarnRate> {
+        self.rule.earn_rates.clone()
+    }
+
+    fn register_interest_terms(&self) -> Vec<InterestTerms> {
+        self.rule.interest_terms.iter().map(|(terms, _)| terms.clone()).collect()
+    }
+
     fn fallback_account(&self) -> String {
         self.rule.fallback.clone()
     }
 
     fn on_account(&self, fac: &mut TransactionFactory, account: &str) -> bool {
         match self.rule.ruleset_main.get(account) {
-            Some(rules) => self.check_rules(fac, rules),
+            Some(rules) => self.check_rules(fac, rules, Some(account), None),
             None => false,
         }
     }
 
-    fn on_reward(&self, fac: &mut TransactionFactory) {
-        if !self.check_rules(fac, &self.rule.ruleset_reward) {
+    fn on_reward(&self, fac: &mut TransactionFactory, ctx: &RewardContext) {
+        if !self.check_rules(fac, &self.rule.ruleset_reward, None, Some(ctx)) {
             fac.set_account(self.rule.fallback.clone());
         }
     }
+
+    fn on_fee(&self, fac: &mut TransactionFactory) {
+        if !self.check_rules(fac, &self.rule.ruleset_fee, None, None) {
+            fac.set_account(self.rule.fee.clone());
+        }
+    }
+
+    fn on_reimbursement(&self, fac: &mut TransactionFactory) {
+        if !self.check_rules(fac, &self.rule.ruleset_reimbursement, None, None) {
+            if let Some(account) = &self.rule.reimbursement {
+                fac.set_account(account.clone());
+            }
+        }
+    }
+
+    fn on_capital_gain(&self, fac: &mut TransactionFactory) {
+        if !self.check_rules(fac, &self.rule.ruleset_capital_gain, None, None) {
+            if let Some(account) = &self.rule.capital_gain {
+                fac.set_account(account.clone());
+            }
+        }
+    }
+
+    fn on_interest(&self, fac: &mut TransactionFactory, ctx: &InterestContext) {
+        if let Some((_, account)) =
+            self.rule.interest_terms.iter().find(|(terms, _)| terms.token == ctx.token)
+        {
+            fac.set_account(account.clone());
+        }
+    }
+
+    fn on_installment(&self, fac: &mut TransactionFactory, _ctx: &InstallmentContext) {
+        if !self.check_rules(fac, &self.rule.ruleset_installment, None, None) {
+            if let Some(account) = &self.rule.installment {
+                fac.set_account(account.clone());
+            }
+        }
+    }
 }
 
 impl TokenMapperImpl {
-    fn check_rules(&self, fac: &mut TransactionFactory, rules: &[CookedRuleEntry]) -> bool {
+    fn check_rules(
+        &self,
+        fac: &mut TransactionFactory,
+        rules: &[CookedRuleEntry],
+        token: Option<&str>,
+        reward: Option<&RewardContext>,
+    ) -> bool {
         let mut matched = false;
 
         'rule_loop: for rule in rules.iter() {
+            if let Some(kind) = rule.reward_kind {
+                if reward.map(|r| r.kind) != Some(kind) {
+                    continue;
+                }
+            }
+
             if rule.side.is_some() {
                 match rule.side.unwrap() {
                     UserRuleSide::Debit => {
@@ -89,6 +163,17 @@ impl TokenMapperImpl {
                 }
             }
 
+            if let Some(statement) = rule.statement {
+                let card = self.rule.cards.iter().find(|c| match token {
+                    Some(token) => c.token == token,
+                    None => fac.check_credit(&[c.token.as_str()]),
+                });
+                let billed = card.map(|c| c.is_billed(fac.date()));
+                if billed != Some(statement == UserRuleStatement::Billed) {
+                    continue;
+                }
+            }
+
             let check_list: Vec<&str> = rule.acc_check_list.iter().map(String::as_ref).collect();
             match rule.acc_check_target {
                 CookedRuleEntryAccCheckTarget::NoOne => (),
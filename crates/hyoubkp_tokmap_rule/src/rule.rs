@@ -4,6 +4,10 @@ use std::{
 };
 
 use hyoubkp_base::error::Result;
+use hyoubkp_base::price::Price;
+use hyoubkp_base::tokmap::{
+    Compounding, DayCount, EarnRate, InterestTerms, RewardKind, StatementCycle,
+};
 
 use crate::many::Many;
 use crate::{AccountToken, AccountTokenOrTag, HintToken, Tag};
@@ -14,6 +18,35 @@ pub enum UserRuleSide {
     Credit,
 }
 
+/// Whether the last statement of the card is still to be paid on the
+/// transaction date, see [`hyoubkp_base::tokmap::StatementCycle::is_billed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRuleStatement {
+    Billed,
+    Unbilled,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRuleRewardKind {
+    Reward,
+    Credit,
+    Cashback,
+    Points,
+}
+
+impl From<UserRuleRewardKind> for RewardKind {
+    fn from(value: UserRuleRewardKind) -> Self {
+        match value {
+            UserRuleRewardKind::Reward => RewardKind::Reward,
+            UserRuleRewardKind::Credit => RewardKind::Credit,
+            UserRuleRewardKind::Cashback => RewardKind::Cashback,
+            UserRuleRewardKind::Points => RewardKind::Points,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct UserRuleRuleEntry {
@@ -35,6 +68,14 @@ pub struct UserRuleRuleEntry {
     #[serde(default)]
     pub credit: Many<AccountTokenOrTag>,
 
+    #[serde(default)]
+    pub reward_kind: Option<UserRuleRewardKind>,
+
+    /// Matched against the card of the token, or of the credit account
+    /// outside the 'main' ruleset
+    #[serde(default)]
+    pub statement: Option<UserRuleStatement>,
+
     #[serde(default)]
     pub account: Option<String>,
 
@@ -49,15 +90,112 @@ pub type UserRuleSetMap = HashMap<String, Vec<UserRuleRuleEntry>>;
 pub struct UserRule {
     pub fallback: String,
 
+    /// Account for fees when no rule in the 'fee' ruleset matches
+    #[serde(default = "UserRule::default_fee")]
+    pub fee: String,
+
+    /// Account for `1200分12` purchases when no rule in the 'installment'
+    /// ruleset matches, `<credit account>:分期` if not set
+    #[serde(default)]
+    pub installment: Option<String>,
+
+    /// Receivable account of `报销` when no rule in the 'reimbursement' ruleset
+    /// matches
+    #[serde(default)]
+    pub reimbursement: Option<String>,
+
+    /// Account of gains and losses realised by selling shares when no rule in
+    /// the 'capital_gain' ruleset matches
+    #[serde(default)]
+    pub capital_gain: Option<String>,
+
     #[serde(default)]
     pub hints: Vec<HintToken>,
 
     #[serde(default)]
     pub tags: HashMap<AccountToken, Vec<Tag>>,
 
+    /// Values of `$name` in prices, like `.set name=value`
+    #[serde(default)]
+    pub variables: HashMap<String, UserRuleVariable>,
+
+    /// Expressions expanded from a leading name, like `.macro name = expr`
+    #[serde(default)]
+    pub macros: HashMap<String, String>,
+
+    /// Billing cycles of credit cards by account token
+    #[serde(default)]
+    pub cards: HashMap<AccountToken, UserRuleCard>,
+
+    /// Points earned by spending on cards by account token
+    #[serde(default)]
+    pub points: HashMap<AccountToken, UserRulePoints>,
+
+    /// Interest of savings and loans by account token
+    #[serde(default)]
+    pub interest: HashMap<AccountToken, UserRuleInterest>,
+
     pub ruleset: UserRuleSetMap,
 }
 
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum UserRuleVariable {
+    Number(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserRuleCard {
+    pub statement_day: u8,
+    /// In the month following the statement if not after `statement_day`
+    pub due_day: u8,
+    pub unbilled: String,
+    pub billed: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserRulePoints {
+    /// Points earned for every whole `per` spent
+    pub per: UserRuleVariable,
+    pub points: UserRuleVariable,
+    /// Account charged for spending on the card
+    pub card: String,
+    pub account: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserRuleInterest {
+    /// Annual rate in percent
+    pub rate: UserRuleVariable,
+    /// `act/365`, `act/360` or `30/360`
+    #[serde(default)]
+    pub day_count: Option<String>,
+    /// `simple`, `daily` or `monthly`
+    #[serde(default)]
+    pub compounding: Option<String>,
+    /// Income account of the interest, or expense account for a loan
+    pub account: String,
+}
+
+impl std::fmt::Display for UserRuleVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserRuleVariable::Number(n) => write!(f, "{}", n),
+            UserRuleVariable::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl UserRule {
+    fn default_fee() -> String {
+        "支出:手续费".into()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum CookedRuleEntryAccCheckTarget {
     NoOne,
@@ -72,17 +210,32 @@ pub struct CookedRuleEntry {
     pub hint_check_list: Vec<HintToken>,
     pub acc_check_target: CookedRuleEntryAccCheckTarget,
     pub acc_check_list: Vec<AccountToken>,
+    pub reward_kind: Option<RewardKind>,
+    pub statement: Option<UserRuleStatement>,
     pub account: String,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct CookedRule {
     pub fallback: String,
+    pub fee: String,
+    pub installment: Option<String>,
+    pub reimbursement: Option<String>,
+    pub capital_gain: Option<String>,
     pub accounts: Vec<AccountToken>,
     pub hints: Vec<HintToken>,
     pub tags: HashMap<Tag, Vec<AccountToken>>,
+    pub variables: Vec<(String, Price)>,
+    pub macros: Vec<(String, String)>,
+    pub cards: Vec<StatementCycle>,
+    pub earn_rates: Vec<EarnRate>,
+    pub interest_terms: Vec<(InterestTerms, String)>,
     pub ruleset_main: HashMap<AccountToken, Vec<CookedRuleEntry>>,
     pub ruleset_reward: Vec<CookedRuleEntry>,
+    pub ruleset_fee: Vec<CookedRuleEntry>,
+    pub ruleset_installment: Vec<CookedRuleEntry>,
+    pub ruleset_reimbursement: Vec<CookedRuleEntry>,
+    pub ruleset_capital_gain: Vec<CookedRuleEntry>,
 }
 
 impl CookedRule {
@@ -132,6 +285,12 @@ impl CookedRule {
                     if !rule.credit.is_empty() {
                         r.credit = rule.credit.clone();
                     }
+                    if rule.reward_kind.is_some() {
+                        r.reward_kind = rule.reward_kind;
+                    }
+                    if rule.statement.is_some() {
+                        r.statement = rule.statement;
+                    }
                     if rule.account.is_some() {
                         r.account = rule.account.clone();
                     }
@@ -212,6 +371,8 @@ impl CookedRule {
                     hint_check_list: rule.hint.iter().map(ToOwned::to_owned).collect(),
                     acc_check_target,
                     acc_check_list: acc_check_set.into_iter().map(ToOwned::to_owned).collect(),
+                    reward_kind: rule.reward_kind.map(Into::into),
+                    statement: rule.statement,
                     account: rule.account.unwrap(),
                 };
 
@@ -237,12 +398,92 @@ impl CookedRule {
 
         let mut cooked = CookedRule {
             fallback: rule.fallback,
+            fee: rule.fee,
+            installment: rule.installment,
+            reimbursement: rule.reimbursement,
+            capital_gain: rule.capital_gain,
             hints: rule.hints,
             ..Default::default()
         };
 
+        for (name, value) in rule.variables.iter() {
+            let price = value.to_string().parse::<Price>().map_err(|e| {
+                hyoubkp_base::err!("variable '{}' is not a price: {}", name, e.message)
+            })?;
+            cooked.variables.push((name.clone(), price));
+        }
+
+        cooked.macros = rule.macros.into_iter().collect();
+
         let mut accounts = HashSet::new();
 
+        for (token, card) in rule.cards.into_iter() {
+            if !(1..=31).contains(&card.statement_day) || !(1..=31).contains(&card.due_day) {
+                hyoubkp_base::bail!("days of card '{}' must be within 1 to 31", token);
+            }
+
+            accounts.insert(token.clone());
+            cooked.cards.push(StatementCycle {
+                token,
+                statement_day: card.statement_day,
+                due_day: card.due_day,
+                unbilled_account: card.unbilled,
+                billed_account: card.billed,
+            });
+        }
+
+        for (token, points) in rule.points.into_iter() {
+            let parse = |value: &UserRuleVariable| {
+                value.to_string().parse::<Price>().map_err(|e| {
+                    hyoubkp_base::err!("points of card '{}' are not a price: {}", token, e.message)
+                })
+            };
+            let (per, earned) = (parse(&points.per)?, parse(&points.points)?);
+            if per <= Price::default() {
+                hyoubkp_base::bail!(
+                    "points of card '{}' must be earned per a positive price",
+                    token
+                );
+            }
+
+            accounts.insert(token.clone());
+            cooked.earn_rates.push(EarnRate {
+                token,
+                per,
+                points: earned,
+                card: points.card,
+                account: points.account,
+            });
+        }
+
+        for (token, interest) in rule.interest.into_iter() {
+            let rate = interest.rate.to_string().parse::<Price>().map_err(|e| {
+                hyoubkp_base::err!("interest rate of '{}' is not a price: {}", token, e.message)
+            })?;
+            let in_token = |e: hyoubkp_base::error::Error| {
+                hyoubkp_base::err!("interest of '{}': {}", token, e.message)
+            };
+            let day_count = match &interest.day_count {
+                Some(s) => s.parse().map_err(in_token)?,
+                None => DayCount::default(),
+            };
+            let compounding = match &interest.compounding {
+                Some(s) => s.parse().map_err(in_token)?,
+                None => Compounding::default(),
+            };
+
+            accounts.insert(token.clone());
+            cooked.interest_terms.push((
+                InterestTerms {
+                    token,
+                    rate,
+                    day_count,
+                    compounding,
+                },
+                interest.account,
+            ));
+        }
+
         for (acc, tags) in rule.tags.into_iter() {
             accounts.insert(acc.clone());
     
@@ -269,6 +510,37 @@ impl CookedRule {
                 .map(|(_, value)| value)
                 .collect();
 
+        if rule.ruleset.contains_key("fee") {
+            cooked.ruleset_fee = Self::parse_ruleset(&cooked.tags, &rule.ruleset, "fee", false)?
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect();
+        }
+
+        if rule.ruleset.contains_key("installment") {
+            cooked.ruleset_installment =
+                Self::parse_ruleset(&cooked.tags, &rule.ruleset, "installment", false)?
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+        }
+
+        if rule.ruleset.contains_key("reimbursement") {
+            cooked.ruleset_reimbursement =
+                Self::parse_ruleset(&cooked.tags, &rule.ruleset, "reimbursement", false)?
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+        }
+
+        if rule.ruleset.contains_key("capital_gain") {
+            cooked.ruleset_capital_gain =
+                Self::parse_ruleset(&cooked.tags, &rule.ruleset, "capital_gain", false)?
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+        }
+
         for (k, v) in cooked.ruleset_main.iter() {
             accounts.insert(k.clone());
             for r in v.iter() {
@@ -278,7 +550,14 @@ impl CookedRule {
             }
         }
 
-        for r in cooked.ruleset_reward.iter() {
+        for r in cooked
+            .ruleset_reward
+            .iter()
+            .chain(cooked.ruleset_fee.iter())
+            .chain(cooked.ruleset_installment.iter())
+            .chain(cooked.ruleset_reimbursement.iter())
+            .chain(cooked.ruleset_capital_gain.iter())
+        {
             for t in r.acc_check_list.iter() {
                 accounts.insert(t.clone());
             }
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use hyoubkp::{
+    datagen::{DataGenDispatch, DataGenKind},
+    executor::Executor,
+    tokmap::TokenMapperKind,
+};
+use hyoubkp_base::datagen::DataGen;
+
+#[test]
+fn test_beancount_tags_and_metadata() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let transactions = executor
+        .parse_expr("中行用餐 20 \"午饭\" #旅行2024 invoice:yes 发票:yes payee:星巴克 '咖啡")
+        .unwrap();
+
+    assert_eq!(
+        DataGenDispatch::new(DataGenKind::Beancount)
+            .gen_to_string(&transactions, 0)
+            .unwrap(),
+        "option \"name_assets\" \"资产\"\n\
+        option \"name_liabilities\" \"负债\"\n\
+        option \"name_equity\" \"权益\"\n\
+        option \"name_income\" \"收入\"\n\
+        option \"name_expenses\" \"支出\"\n\
+        option \"operating_currency\" \"CNY\"\n\
+        \n\
+        2024-01-01 open 支出:用餐\n\
+        2024-01-01 open 负债:信用卡:中行-1234\n\
+        \n\
+        2024-01-01 * \"星巴克\" \"咖啡\" #旅行2024\n\
+        \x20 invoice: \"yes\"\n\
+        \x20 notes: \"发票:yes\"\n\
+        \x20 支出:用餐  20.00 CNY ; 午饭\n\
+        \x20 负债:信用卡:中行-1234  -20.00 CNY ; 午饭\n\
+        \n"
+    );
+}
+
+#[test]
+fn test_beancount_points() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let transactions = executor.parse_expr("建行用餐 40 | 建行兑换 1/100").unwrap();
+
+    let mut datagen = DataGenDispatch::new(DataGenKind::Beancount);
+    datagen.set_option("points=PTS").unwrap();
+
+    assert_eq!(
+        datagen.gen_to_string(&transactions, 1).unwrap(),
+        "2024-01-01 open 支出:用餐\n\
+        2024-01-01 open 资产:积分:建行\n\
+        2024-01-01 open 负债:信用卡:建行-8888\n\
+        2024-01-01 open 收入:积分\n\
+        \n\
+        2024-01-01 * \"\"\n\
+        \x20 支出:用餐  40.00 CNY\n\
+        \x20 资产:积分:建行  2.00 PTS\n\
+        \x20 负债:信用卡:建行-8888  -40.00 CNY\n\
+        \x20 收入:积分  -2.00 PTS\n\
+        \n\
+        2024-01-01 * \"\"\n\
+        \x20 负债:信用卡:建行-8888  1.00 CNY\n\
+        \x20 资产:积分:建行  -100.00 PTS @@ 1.00 CNY\n\
+        \n"
+    );
+}
+
+#[test]
+fn test_beancount_negative_credit() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let transactions = executor.parse_expr("中行用餐 10@12").unwrap();
+
+    let mut datagen = DataGenDispatch::new(DataGenKind::Beancount);
+    datagen.set_option("currency=USD").unwrap();
+    assert!(datagen.set_option("currency=usd").is_err());
+    assert!(datagen.set_option("fee=USD").is_err());
+
+    assert_eq!(
+        datagen.gen_to_string(&transactions, 1).unwrap(),
+        "2024-01-01 open 支出:用餐\n\
+        2024-01-01 open 收入:优惠或礼遇\n\
+        2024-01-01 open 负债:信用卡:中行-1234\n\
+        \n\
+        2024-01-01 * \" FIXME:[中行用餐 10@12]\"\n\
+        \x20 支出:用餐  10.00 USD\n\
+        \x20 收入:优惠或礼遇  2.00 USD\n\
+        \x20 负债:信用卡:中行-1234  -12.00 USD\n\
+        \n"
+    );
+}
+
+#[test]
+fn test_beancount_lots() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-02").unwrap();
+    let mut transactions = executor.commit("工行基金 1000/100").unwrap();
+    executor.parse_directive(".date 2024-03-01").unwrap();
+    transactions.extend(executor.commit("基金工行 1200/-100").unwrap());
+
+    let mut datagen = DataGenDispatch::new(DataGenKind::Beancount);
+    datagen.set_option("shares=FUND").unwrap();
+
+    assert!(datagen.set_option("shares=fund").is_err());
+
+    assert_eq!(
+        datagen.gen_to_string(&transactions, 1).unwrap(),
+        "2024-01-02 open 资产:投资:基金\n\
+        2024-01-02 open 资产:银行:ICBC-工商银行\n\
+        \n\
+        2024-01-02 * \"\"\n\
+        \x20 资产:投资:基金  100.00 FUND {{1000.00 CNY}}\n\
+        \x20 资产:银行:ICBC-工商银行  -1000.00 CNY\n\
+        \n\
+        2024-03-01 open 收入:投资收益\n\
+        \n\
+        2024-03-01 * \"\"\n\
+        \x20 资产:银行:ICBC-工商银行  1200.00 CNY\n\
+        \x20 资产:投资:基金  -100.00 FUND {{1000.00 CNY, 2024-01-02}}\n\
+        \x20 收入:投资收益  -200.00 CNY\n\
+        \n"
+    );
+}
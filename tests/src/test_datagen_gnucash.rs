@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use hyoubkp::{
+    datagen::{DataGenDispatch, DataGenKind},
+    executor::Executor,
+    tokmap::TokenMapperKind,
+};
+use hyoubkp_base::{
+    datagen::DataGen,
+    transaction::{Amount, Entry, Transaction},
+};
+
+#[test]
+fn test_gnucash_memo_and_notes() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let transactions = executor
+        .parse_expr("中行用餐 20 \"午饭\" #旅行2024 payee:食堂 '周一")
+        .unwrap();
+
+    let csv = DataGenDispatch::new(DataGenKind::GnuCash)
+        .gen_to_string(&transactions, 0)
+        .unwrap();
+    let lines: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0][8..], ["Memo", "Notes"]);
+    assert_eq!(
+        lines[1].iter().skip(2).collect::<Vec<_>>(),
+        [
+            "0",
+            "周一",
+            "n",
+            "负债:信用卡:中行 1234",
+            "-20.00",
+            "-20.00",
+            "午饭",
+            "payee:食堂 #旅行2024"
+        ]
+        .iter()
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        lines[2].iter().skip(2).collect::<Vec<_>>(),
+        [
+            "0",
+            "周一",
+            "n",
+            "支出:用餐",
+            "20.00",
+            "20.00",
+            "午饭",
+            "payee:食堂 #旅行2024"
+        ]
+        .iter()
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_gnucash_reconcile() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    let transactions = executor
+        .parse_expr("! 中行用餐 20 | * 中行用餐 20 | ** 中行用餐 20")
+        .unwrap();
+
+    let csv = DataGenDispatch::new(DataGenKind::GnuCash)
+        .gen_to_string(&transactions, 1)
+        .unwrap();
+    let reconcile: Vec<&str> = csv.lines().map(|l| l.split(',').nth(4).unwrap()).collect();
+
+    assert_eq!(reconcile, ["n", "n", "c", "c", "y", "y"]);
+}
+
+#[test]
+fn test_gnucash_undo_and_amend() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let datagen = DataGenDispatch::new(DataGenKind::GnuCash);
+
+    let mut output = Vec::new();
+    for (number, expr) in ["工行用餐 20", "中行用餐 30"].into_iter().enumerate() {
+        let trans = executor.commit(expr).unwrap();
+        datagen.write_to(&mut output, &trans, number as u32).unwrap();
+    }
+    assert_eq!(executor.journal().len(), 2);
+
+    let orig = executor.amend(0, "工行杂项 25").unwrap();
+    assert_eq!(orig.orig_expr.as_deref(), Some("工行用餐 20"));
+    let amended = &executor.journal()[0];
+    assert_eq!(amended.id, orig.id);
+
+    let mut rewritten = Vec::new();
+    datagen
+        .rewrite(output.as_slice(), &mut rewritten, orig.id.as_deref().unwrap(), Some(amended))
+        .unwrap();
+    let rewritten = String::from_utf8(rewritten).unwrap();
+    let lines: Vec<&str> = rewritten.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[1].contains("资产:银行:ICBC 工商银行,-25.00,-25.00"));
+    assert!(lines[2].contains("支出:杂项,25.00,25.00"));
+    assert!(lines[3].contains("中行"));
+
+    let last = executor.undo_last().unwrap();
+    assert_eq!(executor.journal().len(), 1);
+    let mut removed = Vec::new();
+    datagen
+        .rewrite(rewritten.as_bytes(), &mut removed, last.id.as_deref().unwrap(), None)
+        .unwrap();
+    assert_eq!(String::from_utf8(removed).unwrap().lines().count(), 3);
+
+    let balance = executor
+        .ledger()
+        .unwrap()
+        .balance("负债:信用卡:中行 1234", "2024-01-01".parse().unwrap());
+    assert!(balance.values().all(|p| p.as_raw() == 0));
+}
+
+#[test]
+fn test_gnucash_negative_credit() {
+    let entry = |account: &str, amount: &str| Entry {
+        account: account.to_owned(),
+        amount: Amount::Price(amount.parse().unwrap()),
+        memo: None,
+    };
+    let trans = Transaction {
+        debit_entries: vec![entry("资产:现金", "-5")],
+        credit_entries: vec![entry("收入:其他", "-5")],
+        ..Default::default()
+    };
+
+    let csv = DataGenDispatch::new(DataGenKind::GnuCash)
+        .gen_to_string(&[trans], 1)
+        .unwrap();
+    let amounts: Vec<Vec<&str>> = csv
+        .lines()
+        .map(|l| l.split(',').skip(6).take(2).collect())
+        .collect();
+
+    assert_eq!(amounts, [["5.00", "5.00"], ["-5.00", "-5.00"]]);
+}
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 
-use hyoubkp::{executor::Executor, tokmap::TokenMapperKind};
+use hyoubkp::{executor::Executor, interest, schedule::ScheduleState, tokmap::TokenMapperKind};
+use hyoubkp_base::tokmap::{Compounding, DayCount, InterestTerms};
+use hyoubkp_base::transaction::{Commodity, TransactionStatus};
 
 #[test]
 fn test_expr_bank_transfer() {
     let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
     assert_eq!(
-        executor.parse_expr("工行农行 20").unwrap().to_string(),
+        executor.parse_expr("工行农行 20").unwrap()[0].to_string(),
         "Transaction desc: \n\
             资产:银行:ABC 农业银行 debit 20.00\n\
             资产:银行:ICBC 工商银行 credit 20.00\n"
@@ -17,7 +19,7 @@ fn test_expr_bank_transfer() {
 fn test_expr_unmaped_account() {
     let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
     assert_eq!(
-        executor.parse_expr("工行邮储 20").unwrap().to_string(),
+        executor.parse_expr("工行邮储 20").unwrap()[0].to_string(),
         "Transaction desc:  FIXME:[工行邮储 20]\n\
             不平衡的-CNY debit 20.00\n\
             资产:银行:ICBC 工商银行 credit 20.00\n"
@@ -28,7 +30,7 @@ fn test_expr_unmaped_account() {
 fn test_expr_reward() {
     let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
     assert_eq!(
-        executor.parse_expr("工行农行 20-5").unwrap().to_string(),
+        executor.parse_expr("工行农行 20-5").unwrap()[0].to_string(),
         "Transaction desc: \n\
             资产:银行:ABC 农业银行 debit 20.00\n\
             收入:优惠券变现 credit 5.00\n\
@@ -36,7 +38,7 @@ fn test_expr_reward() {
     );
 
     assert_eq!(
-        executor.parse_expr("工行农行 20-5-5").unwrap().to_string(),
+        executor.parse_expr("工行农行 20-5-5").unwrap()[0].to_string(),
         "Transaction desc: \n\
             资产:银行:ABC 农业银行 debit 20.00\n\
             收入:优惠券变现 credit 5.00\n\
@@ -49,7 +51,7 @@ fn test_expr_reward() {
 fn test_expense() {
     let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
     assert_eq!(
-        executor.parse_expr("中行用餐 20-5").unwrap().to_string(),
+        executor.parse_expr("中行用餐 20-5").unwrap()[0].to_string(),
         "Transaction desc: \n\
             支出:用餐 debit 20.00\n\
             收入:优惠或礼遇 credit 5.00\n\
@@ -58,7 +60,7 @@ fn test_expense() {
     assert_eq!(
         executor
             .parse_expr("中行用餐 20-5 10@9@8；农行10")
-            .unwrap()
+            .unwrap()[0]
             .to_string(),
         "Transaction desc: \n\
         支出:用餐 debit 20.00\n\
@@ -79,7 +81,7 @@ fn test_expr_compound() {
     assert_eq!(
         executor
             .parse_expr("工行农行 20-1 30 50@45-10@1 中行 10，建行 5-1 邮储 6.2")
-            .unwrap()
+            .unwrap()[0]
             .to_string(),
         "Transaction desc:  FIXME:[农行 20-1 30 50@45-10@1 中行 10，建行 5-1 邮储 6.2]\n\
             资产:银行:ABC 农业银行 debit 20.00\n\
@@ -101,3 +103,866 @@ fn test_expr_compound() {
             资产:银行:BOC 中国银行 credit 6.20\n"
     );
 }
+
+#[test]
+fn test_expr_step_hints() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert_eq!(
+        executor
+            .parse_expr("中行用餐 20-5券-3积分+2积分")
+            .unwrap()[0]
+            .to_string(),
+        "Expression: 中行用餐 20-5券-3积分+2积分\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 20.00\n\
+            负债:信用卡:中行 1234 debit 2.00\n\
+            收入:优惠券变现 credit 5.00\n\
+            收入:积分变现 credit 3.00\n\
+            负债:信用卡:中行 1234 credit 12.00\n\
+            收入:积分变现 credit 2.00\n"
+    );
+}
+
+#[test]
+fn test_expr_fee() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert_eq!(
+        executor.parse_expr("工行农行 1000!2").unwrap()[0].to_string(),
+        "Expression: 工行农行 1000!2\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            资产:银行:ABC 农业银行 debit 1000.00\n\
+            支出:手续费 debit 2.00\n\
+            资产:银行:ICBC 工商银行 credit 1000.00\n\
+            资产:银行:ICBC 工商银行 credit 2.00\n"
+    );
+
+    assert!(executor.parse_expr("工行农行 1000!2!3").is_err());
+}
+
+#[test]
+fn test_expr_multiple_transactions() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    let transactions = executor
+        .parse_expr("中行用餐 20 '午饭 | 工行农行 100｜中行杂项 5")
+        .unwrap();
+
+    assert_eq!(transactions.len(), 3);
+    assert_eq!(
+        transactions[0].to_string(),
+        "Expression: 中行用餐 20 '午饭\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: 午饭\n\
+            支出:用餐 debit 20.00\n\
+            负债:信用卡:中行 1234 credit 20.00\n"
+    );
+    assert_eq!(
+        transactions[1].to_string(),
+        "Expression: 工行农行 100\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            资产:银行:ABC 农业银行 debit 100.00\n\
+            资产:银行:ICBC 工商银行 credit 100.00\n"
+    );
+    assert_eq!(transactions[2].description.as_deref(), Some(""));
+}
+
+#[test]
+fn test_expr_memo() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert_eq!(
+        executor
+            .parse_expr("中行用餐 20 \"午饭\" 杂项 5 “水” '周一")
+            .unwrap()[0]
+            .to_string(),
+        "Expression: 中行用餐 20 \"午饭\" 杂项 5 “水” '周一\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: 周一\n\
+            支出:用餐 debit 20.00 \"午饭\"\n\
+            支出:杂项 debit 5.00 \"水\"\n\
+            负债:信用卡:中行 1234 credit 20.00 \"午饭\"\n\
+            负债:信用卡:中行 1234 credit 5.00 \"水\"\n"
+    );
+
+    assert!(executor.parse_expr("中行用餐 20 \"午饭").is_err());
+}
+
+#[test]
+fn test_expr_tags_and_metadata() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert_eq!(
+        executor
+            .parse_expr("中行用餐 20 #旅行2024 发票:yes payee:星巴克 '咖啡")
+            .unwrap()[0]
+            .to_string(),
+        "Expression: 中行用餐 20 #旅行2024 发票:yes payee:星巴克 '咖啡\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: 咖啡\n\
+            Payee: 星巴克\n\
+            Tags: #旅行2024\n\
+            Meta: 发票: yes\n\
+            支出:用餐 debit 20.00\n\
+            负债:信用卡:中行 1234 credit 20.00\n"
+    );
+}
+
+#[test]
+fn test_expr_status() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert_eq!(
+        executor.parse_expr("! 中行用餐 20").unwrap()[0].to_string(),
+        "Expression: ! 中行用餐 20\n\
+            Date: 2024-01-01, num base: 0\n\
+            Status: pending\n\
+            Transaction desc: \n\
+            支出:用餐 debit 20.00\n\
+            负债:信用卡:中行 1234 credit 20.00\n"
+    );
+
+    executor.parse_directive(".status cleared").unwrap();
+    let transactions = executor.parse_expr("中行用餐 20 | ** 工行农行 5").unwrap();
+    assert_eq!(transactions[0].status, TransactionStatus::Cleared);
+    assert_eq!(transactions[1].status, TransactionStatus::Reconciled);
+
+    assert!(executor.parse_directive(".status settled").is_err());
+}
+
+#[test]
+fn test_expr_merge_entries() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor.enable_entry_merging();
+    assert_eq!(
+        executor
+            .parse_expr("工行用餐 20 30-5 10，工行用餐 8\"茶\"")
+            .unwrap()[0]
+            .to_string(),
+        "Expression: 工行用餐 20 30-5 10，工行用餐 8\"茶\"\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 68.00 \"20.00 + 30.00 + 10.00 + 8.00 茶\"\n\
+            资产:银行:ICBC 工商银行 credit 63.00 \"20.00 + 25.00 + 10.00 + 8.00 茶\"\n\
+            收入:优惠或礼遇 credit 5.00\n"
+    );
+}
+
+#[test]
+fn test_directives() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+
+    assert!(executor.parse_directive(".date 2024-02-29").is_ok());
+    assert!(executor.parse_directive(".date 2023-02-29").is_err());
+    assert!(executor.parse_directive(".dat 2024-01-01").is_err());
+    assert!(executor.parse_directive(".num x").is_err());
+    assert!(executor.parse_directive(".status  cleared ").is_ok());
+
+    executor.register_directive(hyoubkp::directive::Directive::new(
+        "today",
+        "",
+        "Use the current date",
+        |ex, _| {
+            ex.enable_realtime_date();
+            Ok(())
+        },
+    ));
+    assert!(executor.parse_directive(".today").is_ok());
+    assert!(executor.list_directives().any(|d| d.to_string() == ".date YYYY-MM-DD"));
+}
+
+#[test]
+fn test_directive_state() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+
+    let state = executor.directive_state();
+    executor.parse_directive(".date 2024-02-02").unwrap();
+    executor.parse_directive(".num 5").unwrap();
+    executor.restore_directive_state(state);
+
+    let trans = &executor.parse_expr("中行用餐 1").unwrap()[0];
+    assert_eq!(trans.date.to_string(), "2024-01-01");
+    assert_eq!(trans.num_base, 0);
+}
+
+#[test]
+fn test_expr_variables() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor.parse_directive(".set 午饭=25").unwrap();
+    executor.parse_directive(".set coupon = 5.5").unwrap();
+    assert!(executor.parse_directive(".set 午饭").is_err());
+    assert!(executor.parse_directive(".set a b=1").is_err());
+
+    assert_eq!(
+        executor
+            .parse_expr("工行用餐 $午饭-${coupon}券 | 工行用餐 $午饭x2")
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "Expression: 工行用餐 $午饭-${coupon}券\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 25.00\n\
+            收入:优惠券变现 credit 5.50\n\
+            资产:银行:ICBC 工商银行 credit 19.50\n",
+            "Expression: 工行用餐 $午饭x2\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 25.00\n\
+            支出:用餐 debit 25.00\n\
+            资产:银行:ICBC 工商银行 credit 25.00\n\
+            资产:银行:ICBC 工商银行 credit 25.00\n",
+        ]
+    );
+
+    let e = executor.parse_expr("工行用餐 20-$晚饭").unwrap_err();
+    assert_eq!((e.ch, e.pos, e.len), ('$', 8, 3));
+    let e = executor.parse_expr("工行用餐 20'$晚饭 | 工行用餐 ${晚饭}").unwrap_err();
+    assert_eq!((e.pos, e.len), (19, 5));
+}
+
+#[test]
+fn test_expr_macros() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor
+        .parse_directive(".macro 午饭 = 中行用餐 $1 '食堂午饭")
+        .unwrap();
+    executor.parse_directive(".macro 转 = 工行中行 $2@$1").unwrap();
+    assert!(executor.parse_directive(".macro 工行 = 工行用餐 1").is_err());
+
+    assert_eq!(
+        executor
+            .parse_expr("午饭 18 | ! 转 10 11")
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "Expression: 午饭 18\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: 食堂午饭\n\
+            支出:用餐 debit 18.00\n\
+            负债:信用卡:中行 1234 credit 18.00\n",
+            "Expression: ! 转 10 11\n\
+            Date: 2024-01-01, num base: 0\n\
+            Status: pending\n\
+            Transaction desc: \n\
+            资产:银行:BOC 中国银行 debit 11.00\n\
+            收入:优惠券变现 credit 1.00\n\
+            资产:银行:ICBC 工商银行 credit 10.00\n",
+        ]
+    );
+
+    let e = executor.parse_expr("工行用餐 1 | 午饭").unwrap_err();
+    assert_eq!((e.ch, e.pos, e.len), ('午', 9, 2));
+    let e = executor.parse_expr("午饭 18 加蛋").unwrap_err();
+    assert_eq!((e.ch, e.pos, e.len), ('加', 6, 2));
+    let e = executor.parse_expr("午饭 x").unwrap_err();
+    assert_eq!(e.pos, 3);
+    assert!(executor.parse_expr("工行午饭 1").is_err());
+
+    // Every transaction of a macro with '|' is from the same segment
+    executor
+        .parse_directive(".macro 双 = 中行用餐 $1 | 工行用餐 $1")
+        .unwrap();
+    assert_eq!(
+        executor
+            .parse_expr("双 5 | 农行用餐 7")
+            .unwrap()
+            .iter()
+            .map(|t| t.orig_expr.as_deref().unwrap_or_default())
+            .collect::<Vec<_>>(),
+        ["双 5", "双 5", "农行用餐 7"]
+    );
+}
+
+#[test]
+fn test_expr_history() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    assert!(executor.preview("!!").is_err());
+
+    executor.commit("中行用餐 18-2券 '食堂").unwrap();
+    executor.commit("工行杂项 5").unwrap();
+    assert_eq!(
+        executor.preview("!!").unwrap()[0].orig_expr.as_deref(),
+        Some("工行杂项 5")
+    );
+
+    let trans = &executor.preview("!用餐 25 | ! !杂项").unwrap();
+    assert_eq!(trans[0].orig_expr.as_deref(), Some("中行用餐 25-2券 '食堂"));
+    assert_eq!(
+        trans[0].debit_entries[0].amount.value().to_string(),
+        "25.00"
+    );
+    assert_eq!(trans[1].orig_expr.as_deref(), Some("! 工行杂项 5"));
+    assert_eq!(executor.history().len(), 2);
+
+    executor.commit("!用餐 30").unwrap();
+    assert_eq!(
+        executor.history().last().map(String::as_str),
+        Some("中行用餐 30-2券 '食堂")
+    );
+    executor.undo_last();
+    assert_eq!(
+        executor.history().last().map(String::as_str),
+        Some("工行杂项 5")
+    );
+
+    let e = executor.preview("工行用餐 1 | !交行 1").unwrap_err();
+    assert_eq!((e.ch, e.pos, e.len), ('!', 9, 5));
+    assert!(executor.preview("!! 1").is_err());
+    assert_eq!(executor.preview("!! | !!").unwrap().len(), 2);
+
+    // Only the parsed tokens are matched, not the words of a comment
+    executor.commit("工行杂项 3 '用餐 12").unwrap();
+    assert_eq!(
+        executor.preview("!用餐 25").unwrap()[0].orig_expr.as_deref(),
+        Some("中行用餐 25-2券 '食堂")
+    );
+    assert_eq!(
+        executor.preview("!杂项 4").unwrap()[0].orig_expr.as_deref(),
+        Some("工行杂项 4 '用餐 12")
+    );
+}
+
+#[test]
+fn test_schedules() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor
+        .parse_directive(".every month 31 工行用餐 3500 '房租")
+        .unwrap();
+    executor.parse_directive(".every week 1 工行用餐 10").unwrap();
+    executor.parse_directive(".every year 02-29 工行杂项 1").unwrap();
+    assert!(executor.parse_directive(".every month 32 工行用餐 1").is_err());
+    assert!(executor.parse_directive(".every week 1").is_err());
+    assert!(executor.parse_directive(".every week 1 工行不存在 1").is_err());
+
+    let from = "2023-02-01".parse().unwrap();
+    let to = "2023-03-06".parse().unwrap();
+    assert_eq!(
+        executor
+            .due_schedules(from, to)
+            .iter()
+            .map(|(d, s)| format!("{} {}", d, s))
+            .collect::<Vec<_>>(),
+        [
+            "2023-02-06 week 1 工行用餐 10",
+            "2023-02-13 week 1 工行用餐 10",
+            "2023-02-20 week 1 工行用餐 10",
+            "2023-02-27 week 1 工行用餐 10",
+            "2023-02-28 month 31 工行用餐 3500 '房租",
+            "2023-02-28 year 02-29 工行杂项 1",
+            "2023-03-06 week 1 工行用餐 10",
+        ]
+    );
+
+    let mut state = ScheduleState::default();
+    let (date, schedule) = executor.due_schedules(from, to)[4];
+    let schedule = schedule.clone();
+    let trans = executor.commit_scheduled(&schedule, date).unwrap();
+    assert_eq!(trans[0].date.to_string(), "2023-02-28");
+    assert_eq!(
+        executor.preview("工行用餐 1").unwrap()[0].date.to_string(),
+        "2024-01-01"
+    );
+    state.mark_generated(&schedule, date);
+
+    let mut saved = Vec::new();
+    state.save(&mut saved).unwrap();
+    let state = ScheduleState::load(saved.as_slice()).unwrap();
+    assert!(state.is_generated(&schedule, date));
+    assert!(!state.is_generated(&schedule, "2023-03-31".parse().unwrap()));
+    // Backfilling an earlier range still generates it
+    assert!(!state.is_generated(&schedule, "2023-01-31".parse().unwrap()));
+}
+
+#[test]
+fn test_expr_installment() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-31").unwrap();
+    assert_eq!(
+        executor.commit("中行用餐 100分3!1.5").unwrap()[0].to_string(),
+        "Expression: 中行用餐 100分3!1.5\n\
+            Date: 2024-01-31, num base: 0\n\
+            Transaction desc: \n\
+            Installment: 100.00 in 3 periods, 负债:信用卡:中行 1234:分期 -> 负债:信用卡:中行 1234, \
+            fee 1.50 per period to 支出:手续费\n\
+            支出:用餐 debit 100.00\n\
+            负债:信用卡:中行 1234:分期 credit 100.00\n"
+    );
+    assert!(executor.preview("中行用餐 100分0").is_err());
+    assert!(executor.preview("中行用餐 100分1.5").is_err());
+    assert!(executor.preview("中行用餐 100分2分3").is_err());
+
+    let due = executor.due_installments(
+        "2024-02-01".parse().unwrap(),
+        "2024-12-31".parse().unwrap(),
+    );
+    assert_eq!(
+        due.iter()
+            .map(|(d, _, t)| format!("{} {}", d, t.debit_entries[0].amount))
+            .collect::<Vec<_>>(),
+        ["2024-02-29 33.33", "2024-03-31 33.33"]
+    );
+    assert_eq!(
+        executor
+            .due_installments("2024-01-01".parse().unwrap(), "2024-01-31".parse().unwrap())[0]
+            .2
+            .to_string(),
+        "Date: 2024-01-31, num base: 0\n\
+            Transaction desc: 分期 1/3\n\
+            负债:信用卡:中行 1234:分期 debit 33.34\n\
+            支出:手续费 debit 1.50\n\
+            负债:信用卡:中行 1234 credit 33.34\n\
+            负债:信用卡:中行 1234 credit 1.50\n"
+    );
+
+    // Identical purchases on the same day are different plans
+    executor.commit("中行用餐 100分3!1.5").unwrap();
+    let keys = executor
+        .due_installments("2024-01-31".parse().unwrap(), "2024-01-31".parse().unwrap())
+        .into_iter()
+        .map(|(_, key, _)| key)
+        .collect::<Vec<_>>();
+    assert_eq!(keys.len(), 2);
+    assert_ne!(keys[0], keys[1]);
+
+    // Open plans go on in the following sessions, closed ones are dropped
+    let mut state = ScheduleState::default();
+    for installment in executor.open_installments() {
+        state.add_installment(installment);
+    }
+    for (date, key, _) in executor
+        .due_installments("2024-01-31".parse().unwrap(), "2024-03-31".parse().unwrap())
+    {
+        if key == keys[0] {
+            state.mark_generated(&key, date);
+        }
+    }
+    let mut saved = Vec::new();
+    state.save(&mut saved).unwrap();
+    let state = ScheduleState::load(saved.as_slice()).unwrap();
+    assert_eq!(
+        state.installments().map(|i| &i.key).collect::<Vec<_>>(),
+        [&keys[1]]
+    );
+
+    let mut next = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    next.load_installments(state.installments().cloned());
+    let moves = |executor: &Executor| {
+        executor
+            .due_installments("2024-02-01".parse().unwrap(), "2024-12-31".parse().unwrap())
+            .into_iter()
+            .filter(|(_, key, _)| *key == keys[1])
+            .map(|(_, _, t)| t.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(moves(&next).len(), 2);
+    assert_eq!(moves(&next), moves(&executor));
+
+    executor.undo_last();
+    executor.undo_last();
+    assert!(executor
+        .due_installments("2024-01-01".parse().unwrap(), "2024-12-31".parse().unwrap())
+        .is_empty());
+}
+
+#[test]
+fn test_expr_statement_cycle() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    let mut repaid_to = |date: &str, expr: &str| {
+        executor.parse_directive(format!(".date {}", date)).unwrap();
+        executor.preview(expr).unwrap()[0].debit_entries[0].account.clone()
+    };
+
+    // The statement of the 5th is due on the 25th
+    assert_eq!(repaid_to("2024-01-20", "工行中行还款 100"), "负债:信用卡:中行 1234:已出账单");
+    assert_eq!(repaid_to("2024-01-28", "工行中行还款 100"), "负债:信用卡:中行 1234");
+    assert_eq!(
+        repaid_to("2024-01-20", "工行中行还款未出账单 100"),
+        "负债:信用卡:中行 1234"
+    );
+    assert_eq!(repaid_to("2024-01-20", "工行中行 100"), "资产:银行:BOC 中国银行");
+}
+
+#[test]
+fn test_expr_reimbursement() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-03-01").unwrap();
+    executor.commit("中行用餐报销 50 '客户午餐").unwrap();
+    executor.commit("中行杂项报销 30").unwrap();
+    executor.commit("中行杂项报销 20").unwrap();
+    assert_eq!(executor.open_reimbursements().len(), 3);
+
+    // The claim of the same amount is closed first, then the oldest ones
+    let trans = &executor.commit("工行报销 30").unwrap()[0];
+    assert_eq!(
+        trans.to_string(),
+        "Expression: 工行报销 30\n\
+            Date: 2024-03-01, num base: 0\n\
+            Transaction desc: \n\
+            资产:银行:ICBC 工商银行 debit 30.00\n\
+            资产:应收款:报销 credit 30.00\n"
+    );
+    executor.commit("工行报销 60").unwrap();
+    assert_eq!(
+        executor
+            .open_reimbursements()
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>(),
+        ["2024-03-01 资产:应收款:报销 10.00 [中行杂项报销 20]"]
+    );
+
+    let trans = &executor.preview("工行报销 15").unwrap()[0];
+    assert_eq!(
+        trans.diagnostics,
+        ["Settled 5.00 more than claimed to 资产:应收款:报销"]
+    );
+    assert!(trans.has_build_error);
+    assert_eq!(trans.description.as_deref(), Some(" FIXME:[工行报销 15]"));
+
+    executor.undo_last();
+    assert_eq!(executor.open_reimbursements().len(), 2);
+}
+
+#[test]
+fn test_expr_lots() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-02").unwrap();
+    executor.commit("工行基金 1000/100").unwrap();
+    executor.parse_directive(".date 2024-02-01").unwrap();
+    executor.commit("工行基金 1500/120").unwrap();
+    executor.parse_directive(".date 2024-03-01").unwrap();
+
+    // The oldest lot is sold first
+    let trans = &executor.commit("基金工行 2000/-150").unwrap()[0];
+    assert_eq!(
+        trans.to_string(),
+        "Expression: 基金工行 2000/-150\n\
+            Date: 2024-03-01, num base: 0\n\
+            Transaction desc: \n\
+            资产:银行:ICBC 工商银行 debit 2000.00\n\
+            资产:投资:基金 credit 100.00 / 1000.00 {2024-01-02}\n\
+            资产:投资:基金 credit 50.00 / 625.00 {2024-02-01}\n\
+            收入:投资收益 credit 375.00\n"
+    );
+    assert_eq!(
+        executor
+            .lots("资产:投资:基金")
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>(),
+        ["2024-02-01 70.00 / 875.00"]
+    );
+
+    // A loss is debited to the same account
+    let trans = &executor.preview("基金工行 700/-70").unwrap()[0];
+    assert_eq!(trans.debit_entries[1].account, "收入:投资收益");
+    assert_eq!(trans.debit_entries[1].amount.value().to_string(), "175.00");
+
+    let trans = &executor.preview("基金工行 800/-80").unwrap()[0];
+    assert_eq!(
+        trans.diagnostics,
+        ["Sold 10.00 shares more than held in 资产:投资:基金"]
+    );
+    assert!(trans.has_build_error);
+
+    // Each sale of a transaction takes what the earlier ones left
+    let trans = &executor.preview("基金工行 400/-40*2").unwrap()[0];
+    assert_eq!(
+        trans
+            .credit_entries
+            .iter()
+            .map(|e| e.amount.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "40.00 / 500.00 {2024-02-01}",
+            "30.00 / 375.00 {2024-02-01}",
+            "10.00 / 0.00",
+            "25.00"
+        ]
+    );
+    assert_eq!(
+        trans.diagnostics,
+        ["Sold 10.00 shares more than held in 资产:投资:基金"]
+    );
+
+    executor.undo_last();
+    assert_eq!(executor.lots("资产:投资:基金").len(), 2);
+}
+
+#[test]
+fn test_expr_lots_opening() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    executor.parse_directive(".date 2023-12-31").unwrap();
+    executor.load_opening_balances("基金 1000/100".as_bytes()).unwrap();
+    executor.parse_directive(".date 2024-03-01").unwrap();
+
+    let trans = &executor.commit("基金工行 1200/-100").unwrap()[0];
+    assert!(!trans.has_build_error);
+    assert_eq!(
+        trans
+            .credit_entries
+            .iter()
+            .map(|e| e.amount.to_string())
+            .collect::<Vec<_>>(),
+        ["100.00 / 1000.00 {2023-12-31}", "200.00"]
+    );
+    assert!(executor.lots("资产:投资:基金").is_empty());
+}
+
+#[test]
+fn test_expr_lots_specific() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.parse_directive(".date 2024-01-02").unwrap();
+    executor.commit("工行基金 1000/100").unwrap();
+    executor.parse_directive(".date 2024-02-01").unwrap();
+    executor.commit("工行基金 1500/120").unwrap();
+
+    let trans = &executor
+        .commit("基金工行 1300/-130 lot:2024-02-01")
+        .unwrap()[0];
+    assert_eq!(
+        trans
+            .credit_entries
+            .iter()
+            .map(|e| e.amount.to_string())
+            .collect::<Vec<_>>(),
+        ["120.00 / 1500.00 {2024-02-01}", "10.00 / 100.00 {2024-01-02}"]
+    );
+    assert_eq!(trans.debit_entries[1].amount.value().to_string(), "300.00");
+
+    // Sold lots of the same line are not sold again
+    let transactions = executor
+        .commit("基金工行 450/-45 | 基金工行 450/-45")
+        .unwrap();
+    assert_eq!(
+        transactions[1].credit_entries[0].amount.to_string(),
+        "45.00 / 450.00 {2024-01-02}"
+    );
+    assert!(transactions[1].diagnostics.is_empty());
+}
+
+#[test]
+fn test_expr_lots_amend() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    executor.parse_directive(".date 2024-01-02").unwrap();
+    executor.commit("工行基金 1000/100").unwrap();
+    executor.parse_directive(".date 2024-02-01").unwrap();
+    executor.commit("工行基金 1500/120").unwrap();
+    executor.parse_directive(".date 2024-03-01").unwrap();
+    executor.commit("基金工行 2000/-150").unwrap();
+
+    let lots = |executor: &Executor| {
+        executor
+            .lots("资产:投资:基金")
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+    };
+    let credits = |executor: &Executor| {
+        executor.journal()[2]
+            .credit_entries
+            .iter()
+            .map(|e| e.amount.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // The sale is rebuilt on the lots held before it
+    executor.amend(2, "基金工行 1000/-80").unwrap();
+    assert_eq!(credits(&executor), ["80.00 / 800.00 {2024-01-02}", "200.00"]);
+    assert_eq!(
+        lots(&executor),
+        ["2024-01-02 20.00 / 200.00", "2024-02-01 120.00 / 1500.00"]
+    );
+
+    // Later sales take the lots of the amended purchase
+    executor.amend(0, "工行基金 500/100").unwrap();
+    assert_eq!(credits(&executor), ["80.00 / 400.00 {2024-01-02}", "600.00"]);
+    assert_eq!(
+        lots(&executor),
+        ["2024-01-02 20.00 / 100.00", "2024-02-01 120.00 / 1500.00"]
+    );
+    assert_eq!(
+        executor
+            .ledger()
+            .unwrap()
+            .balance("收入:投资收益", "2024-03-01".parse().unwrap())[&Commodity::Currency]
+            .to_string(),
+        "-600.00"
+    );
+}
+
+#[test]
+fn test_expr_points() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    executor.parse_directive(".date 2024-01-02").unwrap();
+
+    // Points are earned on the amount charged to the card
+    let trans = &executor.commit("建行用餐 70-15").unwrap()[0];
+    assert_eq!(
+        trans.to_string(),
+        "Expression: 建行用餐 70-15\n\
+            Date: 2024-01-02, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 70.00\n\
+            资产:积分:建行 debit 2.00 points\n\
+            收入:优惠或礼遇 credit 15.00\n\
+            负债:信用卡:建行 8888 credit 55.00\n\
+            收入:积分 credit 2.00 points\n"
+    );
+    assert!(executor.preview("工行用餐 70").unwrap()[0]
+        .debit_entries
+        .iter()
+        .all(|e| e.amount.commodity() == Commodity::Currency));
+    // A transfer from the bank account of the token is not spent on the card
+    assert!(executor.preview("建行工行 200").unwrap()[0]
+        .debit_entries
+        .iter()
+        .all(|e| e.amount.commodity() == Commodity::Currency));
+
+    // Redeemed to the card itself without a second account
+    let trans = &executor.commit("建行兑换 5/500").unwrap()[0];
+    assert_eq!(
+        trans.to_string(),
+        "Expression: 建行兑换 5/500\n\
+            Date: 2024-01-02, num base: 0\n\
+            Transaction desc: \n\
+            负债:信用卡:建行 8888 debit 5.00\n\
+            资产:积分:建行 credit 500.00 points / 5.00\n"
+    );
+    assert_eq!(
+        executor
+            .ledger()
+            .unwrap()
+            .balance("资产:积分:建行", "2024-01-02".parse().unwrap())
+            .get(&Commodity::Points)
+            .copied(),
+        Some("-498".parse().unwrap())
+    );
+
+    let trans = &executor.preview("建行兑换 5").unwrap()[0];
+    assert!(trans.has_build_error);
+    let trans = &executor.preview("工行兑换 5/500").unwrap()[0];
+    assert_eq!(trans.diagnostics, ["No points of '工行' to redeem"]);
+}
+
+#[test]
+fn test_interest() {
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    executor
+        .load_opening_balances("工行 10000\n房贷 -500000\n".as_bytes())
+        .unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor.commit("工行用餐 100").unwrap();
+    executor.parse_directive(".date 2024-02-01").unwrap();
+    executor.parse_directive(".interest 2024-01-01").unwrap();
+
+    let journal = executor.journal();
+    assert_eq!(journal.len(), 3);
+    assert_eq!(
+        journal[1].to_string(),
+        "Date: 2024-02-01, num base: 0\n\
+            Transaction desc: 工行 利息 2024-01-01 - 2024-02-01\n\
+            资产:银行:ICBC 工商银行 debit 2.94\n\
+            收入:利息 credit 2.94\n"
+    );
+    assert_eq!(
+        journal[2].to_string(),
+        "Date: 2024-02-01, num base: 0\n\
+            Transaction desc: 房贷 利息 2024-01-01 - 2024-02-01\n\
+            支出:利息 debit 1808.33\n\
+            负债:贷款:房贷 credit 1808.33\n"
+    );
+
+    // The interest posted earns interest in the next period
+    let transactions = executor
+        .accrue_interest("2024-02-01".parse().unwrap(), "2024-03-01".parse().unwrap())
+        .unwrap();
+    assert_eq!(transactions[1].debit_entries[0].amount.to_string(), "1697.78");
+
+    assert!(executor
+        .parse_directive(".interest 2024-03-01 2024-02-01")
+        .is_err());
+
+    // Overlapping periods are not accrued twice
+    let len = executor.journal().len();
+    assert!(executor
+        .parse_directive(".interest 2024-01-15 2024-02-15")
+        .is_err());
+    assert_eq!(executor.journal().len(), len);
+
+    // Undone interest can be accrued again
+    executor.undo_last();
+    executor.undo_last();
+    let transactions = executor
+        .accrue_interest("2024-02-01".parse().unwrap(), "2024-03-01".parse().unwrap())
+        .unwrap();
+    assert_eq!(transactions[1].debit_entries[0].amount.to_string(), "1697.78");
+
+    let mut state = ScheduleState::default();
+    for (token, from, to) in executor.accrued_interest() {
+        state.mark_accrued(token, *from, *to);
+    }
+    let mut saved = Vec::new();
+    state.save(&mut saved).unwrap();
+    let state = ScheduleState::load(saved.as_slice()).unwrap();
+    assert_eq!(state.accrued().count(), 4);
+
+    let mut executor = Executor::new(TokenMapperKind::Example, &HashMap::new()).unwrap();
+    executor.enable_ledger();
+    for (token, from, to) in state.accrued() {
+        executor.mark_interest_accrued(token, from, to);
+    }
+    assert!(executor
+        .accrue_interest("2024-02-20".parse().unwrap(), "2024-03-20".parse().unwrap())
+        .is_err());
+    assert!(executor
+        .accrue_interest("2024-03-01".parse().unwrap(), "2024-04-01".parse().unwrap())
+        .is_ok());
+}
+
+#[test]
+fn test_interest_accrual() {
+    let terms = |day_count, compounding| InterestTerms {
+        token: "工行".into(),
+        rate: "3.65".parse().unwrap(),
+        day_count,
+        compounding,
+    };
+    let accrue = |terms: &InterestTerms, from: &str, to: &str| {
+        interest::accrue(terms, from.parse().unwrap(), to.parse().unwrap(), |_| {
+            "10000".parse().unwrap()
+        })
+        .to_string()
+    };
+
+    let simple = terms(DayCount::Act365, Compounding::Simple);
+    assert_eq!(accrue(&simple, "2023-01-01", "2024-01-01"), "365.00");
+    let daily = terms(DayCount::Act365, Compounding::Daily);
+    assert_eq!(accrue(&daily, "2023-01-01", "2024-01-01"), "371.72");
+    let monthly = terms(DayCount::Act365, Compounding::Monthly);
+    assert_eq!(accrue(&monthly, "2023-01-01", "2023-02-01"), "31.00");
+
+    // Every month counts as 30 days
+    let thirty = terms(DayCount::Thirty360, Compounding::Simple);
+    assert_eq!(accrue(&thirty, "2024-02-01", "2024-03-01"), "30.42");
+    assert_eq!(accrue(&thirty, "2024-01-01", "2024-02-01"), "30.42");
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use hyoubkp::{executor::Executor, tokmap::TokenMapperKind};
+use hyoubkp_base::tokmap::{StatementCycle, TokenMapperOption};
+
+fn new_executor(rule_file: &str) -> Executor {
+    let options = HashMap::from([(
+        TokenMapperOption::RuleFile,
+        format!("{}/rules/{}", env!("CARGO_MANIFEST_DIR"), rule_file),
+    )]);
+    let mut executor = Executor::new(TokenMapperKind::Rule, &options).unwrap();
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor
+}
+
+#[test]
+fn test_rule_reward_kind() {
+    let mut executor = new_executor("basic.toml");
+    assert_eq!(
+        executor
+            .parse_expr("中行用餐 20-5券@12+1")
+            .unwrap()[0]
+            .to_string(),
+        "Expression: 中行用餐 20-5券@12+1\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            支出:用餐 debit 20.00\n\
+            负债:信用卡:中行 1234 debit 1.00\n\
+            收入:优惠券变现 credit 5.00\n\
+            收入:优惠或礼遇 credit 3.00\n\
+            负债:信用卡:中行 1234 credit 12.00\n\
+            收入:返现 credit 1.00\n"
+    );
+}
+
+#[test]
+fn test_rule_fee() {
+    let mut executor = new_executor("basic.toml");
+    assert_eq!(
+        executor.parse_expr("工行中行 100！1").unwrap()[0].to_string(),
+        "Expression: 工行中行 100！1\n\
+            Date: 2024-01-01, num base: 0\n\
+            Transaction desc: \n\
+            负债:信用卡:中行 1234 debit 100.00\n\
+            支出:手续费 debit 1.00\n\
+            资产:银行:ICBC 工商银行 credit 100.00\n\
+            资产:银行:ICBC 工商银行 credit 1.00\n"
+    );
+}
+
+#[test]
+fn test_rule_variables() {
+    let mut executor = new_executor("basic.toml");
+
+    let trans = &executor.parse_expr("工行用餐 $房租 $会员").unwrap()[0];
+    assert_eq!(trans.debit_entries[0].amount.value().to_string(), "3500.00");
+    assert_eq!(trans.debit_entries[1].amount.value().to_string(), "12.50");
+
+    executor.parse_directive(".set 房租=3600").unwrap();
+    let trans = &executor.parse_expr("工行用餐 $房租").unwrap()[0];
+    assert_eq!(trans.debit_entries[0].amount.value().to_string(), "3600.00");
+}
+
+#[test]
+fn test_rule_invalid_variable_name() {
+    let options = HashMap::from([(
+        TokenMapperOption::RuleFile,
+        format!("{}/rules/invalid_names.toml", env!("CARGO_MANIFEST_DIR")),
+    )]);
+    let e = Executor::new(TokenMapperKind::Rule, &options).unwrap_err();
+    assert!(e.message.contains("Invalid variable name '1号'"));
+}
+
+#[test]
+fn test_rule_macros() {
+    let mut executor = new_executor("basic.toml");
+
+    let trans = &executor.parse_expr("交房租").unwrap()[0];
+    assert_eq!(trans.orig_expr.as_deref(), Some("交房租"));
+    assert_eq!(trans.description.as_deref(), Some("房租"));
+    assert_eq!(trans.debit_entries[0].amount.value().to_string(), "3500.00");
+}
+
+#[test]
+fn test_rule_installment() {
+    let mut executor = new_executor("basic.toml");
+    let trans = &executor.parse_expr("中行用餐 1200分12 | 工行用餐 300分3").unwrap();
+    assert_eq!(trans[0].credit_entries[0].account, "负债:信用卡:分期");
+    assert_eq!(trans[0].installments[0].statement_account, "负债:信用卡:中行 1234");
+    assert_eq!(trans[1].credit_entries[0].account, "负债:信用卡:工行分期");
+}
+
+#[test]
+fn test_rule_statement_cycle() {
+    let mut executor = new_executor("basic.toml");
+    executor.enable_ledger();
+    executor.parse_directive(".date 2024-01-20").unwrap();
+    assert_eq!(
+        executor.commit("工行中行 100").unwrap()[0].debit_entries[0].account,
+        "负债:信用卡:中行 1234:已出账单"
+    );
+    executor.parse_directive(".date 2024-01-28").unwrap();
+    assert_eq!(
+        executor.commit("工行中行 100").unwrap()[0].debit_entries[0].account,
+        "负债:信用卡:中行 1234"
+    );
+    executor.commit("中行用餐 130").unwrap();
+
+    let from = "2024-02-01".parse().unwrap();
+    let to = "2024-02-29".parse().unwrap();
+    let due = executor.due_statements(from, to);
+    assert_eq!(due.len(), 1);
+    let (date, cycle) = (due[0].0, due[0].1.clone());
+    assert_eq!(date.to_string(), "2024-02-05");
+    assert_eq!(
+        executor
+            .build_statement(&cycle, date)
+            .unwrap()
+            .unwrap()
+            .to_string(),
+        "Date: 2024-02-05, num base: 0\n\
+            Transaction desc: 中行 账单 2024-02-05\n\
+            负债:信用卡:中行 1234 debit 30.00\n\
+            负债:信用卡:中行 1234:已出账单 credit 30.00\n"
+    );
+
+    let cycle = StatementCycle {
+        statement_day: 20,
+        due_day: 10,
+        ..cycle
+    };
+    assert!(cycle.is_billed("2024-03-10".parse().unwrap()));
+    assert!(!cycle.is_billed("2024-03-11".parse().unwrap()));
+    assert!(!cycle.is_billed("2024-03-20".parse().unwrap()));
+    assert!(cycle.is_billed("2024-03-21".parse().unwrap()));
+}
+
+#[test]
+fn test_rule_reimbursement() {
+    let mut executor = new_executor("basic.toml");
+    let trans = &executor.commit("中行用餐报销 50").unwrap()[0];
+    assert_eq!(trans.debit_entries[0].account, "资产:应收款:公司");
+    assert_eq!(executor.open_reimbursements()[0].amount.to_string(), "50.00");
+}
+
+#[test]
+fn test_rule_capital_gain() {
+    let mut executor = new_executor("basic.toml");
+    executor.commit("工行基金 1000/100").unwrap();
+    let trans = &executor.commit("基金工行 900/-100").unwrap()[0];
+    assert_eq!(trans.debit_entries[1].account, "收入:投资收益");
+    assert_eq!(trans.debit_entries[1].amount.value().to_string(), "100.00");
+}
+
+#[test]
+fn test_rule_points() {
+    let mut executor = new_executor("basic.toml");
+    let trans = &executor.commit("中行用餐 45").unwrap()[0];
+    assert_eq!(trans.debit_entries[1].account, "资产:积分:中行");
+    assert_eq!(trans.debit_entries[1].amount.to_string(), "3.00 points");
+    assert_eq!(trans.credit_entries[1].account, "收入:积分");
+
+    let trans = &executor.commit("中行兑换工行 2/200").unwrap()[0];
+    assert_eq!(trans.credit_entries[0].account, "资产:积分:中行");
+    assert_eq!(trans.debit_entries[0].account, "资产:银行:ICBC 工商银行");
+}
+
+#[test]
+fn test_rule_interest() {
+    let mut executor = new_executor("basic.toml");
+    executor.enable_ledger();
+    executor.load_opening_balances("工行 36000".as_bytes()).unwrap();
+    executor.parse_directive(".date 2024-02-01").unwrap();
+    executor.parse_directive(".interest 2024-01-01").unwrap();
+
+    let trans = &executor.journal()[0];
+    assert_eq!(trans.debit_entries[0].account, "资产:银行:ICBC 工商银行");
+    assert_eq!(trans.credit_entries[0].account, "收入:利息:工行");
+    assert_eq!(trans.credit_entries[0].amount.to_string(), "10.50");
+}
@@ -0,0 +1,179 @@
+use hyoubkp_base::price::Price;
+use hyoubkp_base::transaction::{Amount, Commodity, Entry, Transaction};
+
+fn entry(account: &str, amount: Amount) -> Entry {
+    Entry {
+        account: account.into(),
+        amount,
+        memo: None,
+    }
+}
+
+#[test]
+fn test_transaction_balance() {
+    let mut trans = Transaction {
+        debit_entries: vec![entry(
+            "资产:基金",
+            Amount::Shares(Price::new_unchecked(10, 0), Price::new_unchecked(100, 0)),
+        )],
+        credit_entries: vec![entry(
+            "资产:银行",
+            Amount::Price(Price::new_unchecked(100, 0)),
+        )],
+        ..Default::default()
+    };
+    assert!(trans.imbalances().is_empty());
+
+    trans.credit_entries.push(entry(
+        "收入:其他",
+        Amount::Price(Price::new_unchecked(2, 50)),
+    ));
+    assert_eq!(
+        trans.imbalances(),
+        [(Commodity::Currency, -Price::new_unchecked(2, 50))]
+    );
+
+    trans.check_balance(Some("不平衡的-CNY"));
+    assert!(trans.has_build_error);
+    assert_eq!(
+        trans.diagnostics,
+        ["Debit and credit differ by -2.50 in CNY"]
+    );
+    assert_eq!(trans.debit_entries[1].account, "不平衡的-CNY");
+    assert_eq!(
+        trans.debit_entries[1].amount.value(),
+        Price::new_unchecked(2, 50)
+    );
+    assert!(trans.imbalances().is_empty());
+}
+
+#[test]
+fn test_transaction_invalid_price() {
+    let mut executor = hyoubkp::executor::Executor::new(
+        hyoubkp::tokmap::TokenMapperKind::Example,
+        &Default::default(),
+    )
+    .unwrap();
+    let trans = &executor.parse_expr("中行用餐 20-30").unwrap()[0];
+
+    assert!(trans.has_build_error);
+    assert_eq!(trans.diagnostics.len(), 1);
+}
+
+#[test]
+fn test_price_negative() {
+    let price: Price = "-5.50".parse().unwrap();
+    assert_eq!(price, -Price::new_unchecked(5, 50));
+    assert_eq!(price.to_string(), "-5.50");
+    assert_eq!((-Price::new_unchecked(0, 5)).to_string(), "-0.05");
+    assert!("--5".parse::<Price>().is_err());
+}
+
+#[test]
+fn test_balance_assertion() {
+    let mut executor = hyoubkp::executor::Executor::new(
+        hyoubkp::tokmap::TokenMapperKind::Example,
+        &Default::default(),
+    )
+    .unwrap();
+    executor.enable_ledger();
+    executor
+        .load_opening_balances("# opening\n工行 1000\n资产:现金 50.5\n".as_bytes())
+        .unwrap();
+
+    executor.parse_expr("工行用餐 20").unwrap();
+    executor.parse_expr("中行用餐 10.25").unwrap();
+
+    assert!(executor.parse_directive(".balance 工行 980").is_ok());
+    assert!(executor.parse_directive(".balance 中行 -10.25").is_ok());
+    assert!(executor.parse_directive(".balance 资产:现金 50.50").is_ok());
+
+    let e = executor.parse_directive(".balance 工行 1000").unwrap_err();
+    assert!(e.message.contains("difference 20.00"));
+    assert!(executor.parse_directive(".balance 工行").is_err());
+}
+
+#[test]
+fn test_ledger_query() {
+    let mut executor = hyoubkp::executor::Executor::new(
+        hyoubkp::tokmap::TokenMapperKind::Example,
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(executor.ledger().is_none());
+    executor.enable_ledger();
+    executor
+        .load_opening_balances("工行 100".as_bytes())
+        .unwrap();
+
+    executor.parse_directive(".date 2024-01-01").unwrap();
+    executor.parse_expr("工行用餐 20\"午饭\"").unwrap();
+    executor.parse_directive(".date 2024-01-03").unwrap();
+    executor.parse_expr("工行用餐 30").unwrap();
+
+    // Entered out of order
+    executor.parse_directive(".date 2024-01-02").unwrap();
+    assert!(executor.parse_directive(".balance 工行 80").is_ok());
+
+    let ledger = executor.ledger().unwrap();
+    let account = "资产:银行:ICBC 工商银行";
+    let balance = |date: &str| ledger.balance(account, date.parse().unwrap());
+    assert_eq!(
+        balance("2023-12-31"),
+        [(Commodity::Currency, Price::new_unchecked(100, 0))].into()
+    );
+    assert_eq!(
+        balance("2024-01-02"),
+        [(Commodity::Currency, Price::new_unchecked(80, 0))].into()
+    );
+    assert_eq!(
+        balance("2024-01-03"),
+        [(Commodity::Currency, Price::new_unchecked(50, 0))].into()
+    );
+
+    let postings = ledger.postings(account);
+    assert_eq!(postings.len(), 2);
+    assert_eq!(postings[0].amount, -Price::new_unchecked(20, 0));
+    assert_eq!(postings[0].memo.as_deref(), Some("午饭"));
+    assert!(ledger.postings("资产:现金").is_empty());
+
+    executor.disable_ledger();
+    assert!(executor.ledger().is_none());
+    assert!(executor.parse_directive(".balance 工行 50").is_err());
+}
+
+#[test]
+fn test_preview_and_commit() {
+    let mut executor = hyoubkp::executor::Executor::new(
+        hyoubkp::tokmap::TokenMapperKind::Example,
+        &Default::default(),
+    )
+    .unwrap();
+    executor.enable_ledger();
+    let account = "资产:银行:ICBC 工商银行";
+
+    let preview = executor.preview("工行用餐 20").unwrap();
+    assert_eq!(preview.len(), 1);
+    assert!(executor.ledger().unwrap().postings(account).is_empty());
+
+    executor.commit("工行用餐 20").unwrap();
+    assert_eq!(executor.ledger().unwrap().postings(account).len(), 1);
+
+    let previewed = executor.preview_directive(".date 2024-03-01").unwrap();
+    assert_eq!(
+        executor.preview_with(&previewed, "工行用餐 1").unwrap()[0].date.to_string(),
+        "2024-03-01"
+    );
+    assert_ne!(
+        executor.preview("工行用餐 1").unwrap()[0].date.to_string(),
+        "2024-03-01"
+    );
+    assert!(executor.preview_directive(".date 2024-13-01").is_err());
+    assert!(executor.preview_directive(".balance 工行 20").is_err());
+
+    assert!(executor.preview_directive(".set 1号=5").is_err());
+
+    let previewed = executor.preview_directive(".macro 午饭 = 工行用餐 $1").unwrap();
+    assert_eq!(executor.preview_with(&previewed, "午饭 5").unwrap().len(), 1);
+    assert!(executor.preview("午饭 5").is_err());
+}